@@ -12,14 +12,36 @@ fn main() {
         Path::new(&current_dir).join("target/debug")
     };
 
-    Command::new("rustc")
-        .arg("src/test_shared.rs")
-        .arg("--crate-name")
-        .arg("test_shared")
-        .arg("--crate-type")
-        .arg("dylib")
-        .arg("--out-dir")
-        .arg(target)
+    // Fingerprint of the build, see
+    // src/fingerprint.rs
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let version = Command::new(&rustc)
+        .arg("-V")
         .output()
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
         .unwrap_or_else(|e| panic!("failed to execute process: {}", e));
+    let triple = env::var("TARGET").unwrap_or_default();
+    let build_profile = env::var("PROFILE").unwrap_or_default();
+
+    println!("cargo:rustc-env=DR_RUSTC_VERSION={}", version);
+    println!("cargo:rustc-env=DR_TARGET={}", triple);
+    println!("cargo:rustc-env=DR_PROFILE={}", build_profile);
+
+    let names = ["test_shared", "test_state", "test_plugin", "test_hot", "test_fingerprint"];
+
+    for name in &names {
+        Command::new(&rustc)
+            .arg(format!("src/{}.rs", name))
+            .arg("--crate-name")
+            .arg(name)
+            .arg("--crate-type")
+            .arg("dylib")
+            .arg("--out-dir")
+            .arg(&target)
+            .env("DR_RUSTC_VERSION", &version)
+            .env("DR_TARGET", &triple)
+            .env("DR_PROFILE", &build_profile)
+            .output()
+            .unwrap_or_else(|e| panic!("failed to execute process: {}", e));
+    }
 }
@@ -0,0 +1,197 @@
+//! Procedural macros for
+//! [dynamic_reload](https://docs.rs/dynamic_reload).
+//! Use them through the re-exports in
+//! dynamic_reload instead of depending on this
+//! crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Abi, Error, FnArg, ForeignItem, ForeignItemFn, Item, ItemMod, LitStr, Pat,
+};
+
+/// Turns the functions declared in the
+/// ```extern``` blocks of a module into wrappers
+/// that call the current version of a reloadable
+/// library.
+///
+/// The module gets a
+/// ```HOT_LIB: dynamic_reload::HotModule```
+/// static which has to be loaded into a
+/// ```DynamicReload``` before the functions are
+/// called. Every call goes to the current version
+/// of the library and keeps that version loaded
+/// until the call returns. The function is only
+/// looked up again after a reload. Calling a
+/// function panics if the library isn't loaded
+/// or doesn't export it.
+///
+/// The wrappers are ```unsafe fn```s like the
+/// functions of a regular ```extern``` block. The
+/// caller has to make sure the declared
+/// signature matches the export of every version
+/// of the library.
+///
+/// Items other than ```extern``` blocks are kept
+/// as they are. ```#[link_name = "..."]``` sets
+/// the name of the export to look up.
+///
+/// # Examples
+///
+/// ```ignore
+/// #[dynamic_reload::hot_module(dylib = "game_logic")]
+/// mod hot {
+///     extern "Rust" {
+///         pub fn step(state: &mut State, dt: f32);
+///     }
+/// }
+///
+/// unsafe { hot::HOT_LIB.load(&mut reload_handler)? };
+/// unsafe { hot::step(&mut state, 0.016) };
+/// ```
+#[proc_macro_attribute]
+pub fn hot_module(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut dylib = None;
+
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("dylib") {
+            dylib = Some(meta.value()?.parse::<LitStr>()?);
+            Ok(())
+        } else {
+            Err(meta.error("unsupported hot_module argument, expected `dylib`"))
+        }
+    });
+
+    parse_macro_input!(args with parser);
+    let module = parse_macro_input!(item as ItemMod);
+
+    match expand(dylib, module) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(dylib: Option<LitStr>, module: ItemMod) -> syn::Result<TokenStream2> {
+    let dylib = dylib.ok_or_else(|| {
+        Error::new(
+            Span::call_site(),
+            "missing library name, use `dylib = \"name\"`",
+        )
+    })?;
+
+    let items = match module.content.as_ref() {
+        Some((_, items)) => items,
+        None => {
+            return Err(Error::new_spanned(
+                &module,
+                "hot_module needs an inline module",
+            ))
+        }
+    };
+
+    let mut expanded = Vec::new();
+
+    for item in items {
+        match item {
+            Item::ForeignMod(foreign) => {
+                for foreign_item in foreign.items.iter() {
+                    match foreign_item {
+                        ForeignItem::Fn(fun) => expanded.push(wrapper(&foreign.abi, fun)?),
+                        other => {
+                            return Err(Error::new_spanned(
+                                other,
+                                "only functions are supported in hot_module",
+                            ))
+                        }
+                    }
+                }
+            }
+            other => expanded.push(quote!(#other)),
+        }
+    }
+
+    let ItemMod {
+        attrs, vis, ident, ..
+    } = &module;
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis mod #ident {
+            /// The library the functions of this
+            /// module are called in.
+            pub static HOT_LIB: ::dynamic_reload::HotModule =
+                ::dynamic_reload::HotModule::new(#dylib);
+
+            #(#expanded)*
+        }
+    })
+}
+
+fn wrapper(abi: &Abi, fun: &ForeignItemFn) -> syn::Result<TokenStream2> {
+    let sig = &fun.sig;
+
+    if let Some(variadic) = sig.variadic.as_ref() {
+        return Err(Error::new_spanned(
+            variadic,
+            "variadic functions aren't supported",
+        ));
+    }
+
+    let mut args = Vec::new();
+    let mut types = Vec::new();
+
+    for (i, input) in sig.inputs.iter().enumerate() {
+        match input {
+            FnArg::Typed(arg) => {
+                let name = match arg.pat.as_ref() {
+                    Pat::Ident(pat) => pat.ident.clone(),
+                    _ => format_ident!("arg{}", i),
+                };
+                args.push(name);
+                types.push(arg.ty.clone());
+            }
+            FnArg::Receiver(receiver) => {
+                return Err(Error::new_spanned(receiver, "methods aren't supported"))
+            }
+        }
+    }
+
+    let vis = &fun.vis;
+    let name = &sig.ident;
+    let output = &sig.output;
+    let mut symbol = name.to_string();
+    let ty = quote!(unsafe #abi fn(#(#types),*) #output);
+    let mut attrs = Vec::new();
+
+    // #[link_name] picks the export to look up
+    // like it does for regular extern blocks
+    for attr in fun.attrs.iter() {
+        if attr.path().is_ident("link_name") {
+            let value = &attr.meta.require_name_value()?.value;
+            symbol = syn::parse2::<LitStr>(quote!(#value))?.value();
+        } else {
+            attrs.push(attr);
+        }
+    }
+
+    let symbol = LitStr::new(&format!("{}\0", symbol), Span::call_site());
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis unsafe fn #name(#(#args: #types),*) #output {
+            static FUN: ::dynamic_reload::HotSymbol<#ty> =
+                ::dynamic_reload::HotSymbol::new(#symbol);
+
+            let lib = HOT_LIB.lib();
+            let _guard = lib.enter();
+
+            // The caller vouches for the signature
+            // of the export
+            unsafe {
+                let fun = FUN.get(&lib);
+                fun(#(#args),*)
+            }
+        }
+    })
+}
@@ -1,6 +1,6 @@
 extern crate dynamic_reload;
 
-use dynamic_reload::{DynamicReload, Lib, PlatformName, Search, Symbol, UpdateState};
+use dynamic_reload::{DynamicReload, Lib, PlatformName, Search, UpdateState};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
@@ -31,7 +31,27 @@ impl Plugins {
         match state {
             UpdateState::Before => Self::unload_plugins(self, lib.unwrap()),
             UpdateState::After => Self::reload_plugin(self, lib.unwrap()),
-            UpdateState::ReloadFailed(_) => println!("Failed to reload"),
+            UpdateState::ReloadFailed(e) => {
+                println!("Failed to reload, err {:?}", e);
+
+                // The old version is still active so keep using it. There is none when
+                // a library found in a plugin directory or waiting for its file fails
+                if let Some(lib) = lib {
+                    Self::reload_plugin(self, lib);
+                }
+            }
+            UpdateState::Unchanged => (),
+            UpdateState::UnloadTimedOut => println!("Old version still in use, leaked"),
+            UpdateState::WatchError(e) => println!("File watcher failed, err {:?}", e),
+            UpdateState::Removed => println!("Library removed, keeping the loaded version"),
+            UpdateState::Renamed(to) => println!("Library renamed to {:?}", to),
+            UpdateState::Added => Self::add_plugin(self, lib.unwrap()),
+            UpdateState::StateMismatch(saved, expected) => {
+                println!(
+                    "State version {} not restored, expected {}",
+                    saved, expected
+                )
+            }
         }
     }
 }
@@ -44,21 +64,43 @@ fn main() {
     // Setup the reload handler. A temporary directory will be created inside the target/debug
     // where plugins will be loaded from. That is because on some OS:es loading a shared lib
     // will lock the file so we can't overwrite it so this works around that issue.
-    let mut reload_handler = DynamicReload::new(
-        Some(vec!["target/debug"]),
-        Some("target/debug"),
-        Search::Default,
-        Duration::from_secs(2),
-    );
+    let mut reload_handler = match DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .search(Search::Default)
+        .debounce(Duration::from_secs(2))
+        .build()
+    {
+        Ok(reload_handler) => reload_handler,
+        Err(e) => {
+            println!("Unable to setup reload handler, err {:?}", e);
+            return;
+        }
+    };
 
     // test_shared is generated in build.rs
-    match unsafe { reload_handler.add_library("test_shared", PlatformName::Yes) } {
-        Ok(lib) => plugs.add_plugin(&lib),
+    let lib = match unsafe { reload_handler.add_library("test_shared", PlatformName::Yes) } {
+        Ok(lib) => lib,
         Err(e) => {
             println!("Unable to load dynamic lib, err {:?}", e);
             return;
         }
-    }
+    };
+
+    plugs.add_plugin(&lib);
+
+    // The symbol is looked up again after the library has been reloaded so it's safe to keep
+    // it around instead of calling lib.get every time
+    let mut fun =
+        match unsafe { reload_handler.symbol::<extern "C" fn() -> i32>(&lib, "shared_fun") } {
+            Ok(fun) => fun,
+            Err(e) => {
+                println!("Unable to find symbol, err {:?}", e);
+                return;
+            }
+        };
+
+    drop(lib);
 
     // While this is running (printing a constant number) change return value in file src/test_shared.rs
     // build the project with cargo build and notice that this code will now return the new value
@@ -67,13 +109,9 @@ fn main() {
             reload_handler.update(&Plugins::reload_callback, &mut plugs);
         }
 
-        if !plugs.plugins.is_empty() {
-            // In a real program you want to cache the symbol and not do it every time if your
-            // application is performance critical
-            let fun: Symbol<extern "C" fn() -> i32> =
-                unsafe { plugs.plugins[0].lib.get(b"shared_fun\0").unwrap() };
-
-            println!("Value {}", fun());
+        match fun.get() {
+            Ok(fun) => println!("Value {}", fun()),
+            Err(e) => println!("Unable to call shared_fun, err {:?}", e),
         }
 
         // Wait for 0.5 sec
@@ -0,0 +1,321 @@
+use crate::lifecycle::HostContext;
+use crate::{DeletePolicy, DynamicReload, PathMatch, Result, Search, UnloadPolicy, WatchMode};
+use std::{
+    ffi::c_void,
+    path::{Path, PathBuf},
+    sync::mpsc::channel,
+    time::Duration,
+};
+
+/// Builder used to configure and create a
+/// [DynamicReload](struct.DynamicReload.html)
+/// instance.
+///
+/// Created with
+/// [DynamicReload::builder](struct.DynamicReload.html#method.builder).
+///
+/// # Examples
+///
+/// ```ignore
+/// let reload_handler = DynamicReload::builder()
+///     .search_path("target/debug")
+///     .shadow_dir("target/debug")
+///     .search(Search::Backwards)
+///     .debounce(Duration::from_secs(2))
+///     .build()?;
+/// ```
+#[derive(Debug)]
+pub struct DynamicReloadBuilder {
+    search_paths: Vec<PathBuf>,
+    shadow_dir: Option<PathBuf>,
+    search: Search,
+    debounce: Duration,
+    unload: UnloadPolicy,
+    timestamps: bool,
+    path_match: PathMatch,
+    unload_timeout: Duration,
+    watch_mode: WatchMode,
+    poll_interval: Duration,
+    delete_policy: DeletePolicy,
+    host_context: HostContext,
+    api_version: Option<u32>,
+}
+
+impl Default for DynamicReloadBuilder {
+    fn default() -> Self {
+        DynamicReloadBuilder {
+            search_paths: Vec::new(),
+            shadow_dir: None,
+            search: Search::Default,
+            debounce: Duration::from_secs(2),
+            unload: if cfg!(feature = "no-unload") {
+                UnloadPolicy::Keep
+            } else {
+                UnloadPolicy::Unload
+            },
+            timestamps: !cfg!(feature = "no-timestamps"),
+            path_match: PathMatch::Exact,
+            unload_timeout: Duration::from_secs(1),
+            watch_mode: WatchMode::Auto,
+            poll_interval: Duration::from_secs(1),
+            delete_policy: DeletePolicy::Keep,
+            host_context: HostContext::default(),
+            api_version: None,
+        }
+    }
+}
+
+impl DynamicReloadBuilder {
+    /// Adds an extra path that
+    /// [add_library](struct.DynamicReload.html#method.add_library)
+    /// will look for shared libraries in.
+    pub fn search_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.search_paths.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Adds a list of extra paths that
+    /// [add_library](struct.DynamicReload.html#method.add_library)
+    /// will look for shared libraries in.
+    pub fn search_paths<I, P>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.search_paths
+            .extend(paths.into_iter().map(|p| p.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Location where a temporary directory
+    /// will be created to keep a copy of all the
+    /// shared libraries and load from there.
+    ///
+    /// Some operating systems locks loaded
+    /// shared libraries which would make it
+    /// impossible to update them. By having a
+    /// separate directory DynamicReload will
+    /// look for changes in the original path
+    /// while having them loaded from another.
+    ///
+    /// Without a shadow directory libraries are
+    /// loaded from where they are found. This is
+    /// only supported for reloading on Linux, and
+    /// only when the new version is written to a
+    /// new file. Other changes are reported with
+    /// ```Error::ShadowDirRequired```
+    pub fn shadow_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.shadow_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// How to search for libraries relative to
+    /// the executable. Defaults to
+    /// ```Search::Default```
+    pub fn search(mut self, search: Search) -> Self {
+        self.search = search;
+        self
+    }
+
+    /// Duration that the watcher will wait after
+    /// the library changed on disk until it will
+    /// cause a reload. (Multiple write calls
+    /// could be made to the library until it is
+    /// fully written.) Defaults to 2 seconds.
+    ///
+    /// The wait is extended while the size or
+    /// modification time of the file keeps
+    /// changing, and cut short when the file is
+    /// closed after being written (on platforms
+    /// that report it).
+    pub fn debounce(mut self, duration: Duration) -> Self {
+        self.debounce = duration;
+        self
+    }
+
+    /// What to do with the old version of a
+    /// library once it has been replaced.
+    /// Defaults to ```UnloadPolicy::Unload```
+    /// unless the ```no-unload``` feature is
+    /// enabled.
+    pub fn unload_policy(mut self, unload: UnloadPolicy) -> Self {
+        self.unload = unload;
+        self
+    }
+
+    /// Prefix shadow copies with a timestamp.
+    /// Every version is copied to a file of its
+    /// own either way, named after the id and
+    /// generation of the library. Defaults to
+    /// ```true``` unless the ```no-timestamps```
+    /// feature is enabled.
+    pub fn timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Which file events cause a library to be
+    /// reloaded. Defaults to ```PathMatch::Exact```
+    pub fn path_match(mut self, path_match: PathMatch) -> Self {
+        self.path_match = path_match;
+        self
+    }
+
+    /// How long a reload or removal waits for
+    /// calls into the old version of a library
+    /// to finish (see
+    /// [Lib::enter](struct.Lib.html#method.enter))
+    /// before the library is leaked instead of
+    /// unloaded. Defaults to 1 second.
+    pub fn unload_timeout(mut self, timeout: Duration) -> Self {
+        self.unload_timeout = timeout;
+        self
+    }
+
+    /// How changes to libraries are detected.
+    /// Defaults to ```WatchMode::Auto``` which
+    /// falls back to polling when the native file
+    /// watcher fails.
+    pub fn watch_mode(mut self, mode: WatchMode) -> Self {
+        self.watch_mode = mode;
+        self
+    }
+
+    /// How often libraries are checked for
+    /// changes when polling is used. Defaults to
+    /// 1 second.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// What to do with a library when its file is
+    /// removed or renamed. Defaults to
+    /// ```DeletePolicy::Keep```
+    pub fn delete_policy(mut self, policy: DeletePolicy) -> Self {
+        self.delete_policy = policy;
+        self
+    }
+
+    /// Pointer passed to the optional
+    /// ```dr_plugin_init``` export of every
+    /// library. Defaults to null.
+    ///
+    /// Libraries can export
+    ///
+    /// ```ignore
+    /// #[no_mangle]
+    /// pub extern "C" fn dr_plugin_init(host_ctx: *mut c_void) -> i32
+    ///
+    /// #[no_mangle]
+    /// pub extern "C" fn dr_plugin_shutdown()
+    /// ```
+    ///
+    /// ```dr_plugin_init``` is called when a
+    /// library is added and after each reload
+    /// and returns 0 on success. Any other value
+    /// is reported as ```Error::PluginInit```, on
+    /// a reload the old version is initialized
+    /// again and kept. ```dr_plugin_shutdown``` is
+    /// called before a reload replaces the
+    /// library and when it's removed.
+    ///
+    /// # Safety
+    /// ```ctx``` has to stay valid for as long as
+    /// libraries are loaded and has to be usable
+    /// from the thread doing the reloads.
+    pub unsafe fn host_context(mut self, ctx: *mut c_void) -> Self {
+        self.host_context = HostContext(ctx);
+        self
+    }
+
+    /// Version of the API between the host and
+    /// its libraries. Libraries exporting a
+    /// [BuildFingerprint](struct.BuildFingerprint.html)
+    /// with another version are closed again
+    /// right after being opened and rejected with
+    /// ```Error::Incompatible```, as are libraries
+    /// built with another rustc version, target
+    /// or profile than the host. Libraries
+    /// without a fingerprint are always loaded.
+    ///
+    /// Without an API version only the build is
+    /// compared.
+    pub fn api_version(mut self, version: u32) -> Self {
+        self.api_version = Some(version);
+        self
+    }
+
+    /// Creates the
+    /// [DynamicReload](struct.DynamicReload.html)
+    /// instance.
+    ///
+    /// Fails if the file watcher can't be
+    /// created or if the shadow directory can't
+    /// be created.
+    pub fn build(self) -> Result<DynamicReload> {
+        self.finish(true)
+    }
+
+    /// Same as ```build``` but errors are printed
+    /// and the failing part is disabled. Only
+    /// used by ```DynamicReload::new```
+    pub(crate) fn build_lenient(self) -> DynamicReload {
+        // Can't fail when not strict
+        self.finish(false).unwrap()
+    }
+
+    fn finish(self, strict: bool) -> Result<DynamicReload> {
+        let (tx, rx) = channel();
+
+        let watcher = match DynamicReload::get_watcher(
+            tx,
+            self.debounce,
+            self.watch_mode,
+            self.poll_interval,
+        ) {
+            Ok(watcher) => Some(watcher),
+            Err(e) if !strict => {
+                println!(
+                    "Unable to create file watcher, no dynamic reloading will be done, \
+                     error: {:?}",
+                    e
+                );
+                None
+            }
+            Err(e) => return Err(e),
+        };
+
+        let shadow_dir = match DynamicReload::get_temp_dir(self.shadow_dir.as_deref()) {
+            Ok(dir) => dir,
+            Err(e) if !strict => {
+                println!("Unable to create tempdir {}", e);
+                None
+            }
+            Err(e) => return Err(e),
+        };
+
+        #[cfg(feature = "async")]
+        let (rx, async_state) = crate::events::AsyncState::bridge(rx);
+
+        Ok(DynamicReload {
+            libs: Vec::new(),
+            watcher,
+            shadow_dir,
+            search_paths: DynamicReload::canonicalize_paths(self.search_paths),
+            watch_recv: rx,
+            search: self.search,
+            unload: self.unload,
+            timestamps: self.timestamps,
+            path_match: self.path_match,
+            unload_timeout: self.unload_timeout,
+            delete_policy: self.delete_policy,
+            waiting: Vec::new(),
+            dirs: Vec::new(),
+            host_context: self.host_context,
+            api_version: self.api_version,
+            #[cfg(feature = "async")]
+            async_state,
+        })
+    }
+}
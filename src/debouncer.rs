@@ -0,0 +1,193 @@
+use crate::Error;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Event reported by a ```WatchBackend``` before
+/// it has been debounced.
+#[derive(Debug)]
+pub(crate) enum RawEvent {
+    /// The file was created or written to.
+    Modified(PathBuf),
+    /// A file that was opened for writing has
+    /// been closed, so it's most likely done.
+    WriteClosed(PathBuf),
+    /// The file was removed.
+    Removed(PathBuf),
+    /// The file was renamed (from, to).
+    Renamed(PathBuf, PathBuf),
+    /// Events may have been lost.
+    Rescan,
+    /// The backend failed.
+    Error(Error),
+}
+
+/// Debounced event handled by
+/// [update](struct.DynamicReload.html#method.update).
+#[derive(Debug)]
+pub(crate) enum WatchEvent {
+    /// The file has been written and is no
+    /// longer changing.
+    Changed(PathBuf),
+    /// The file was removed and hasn't come back
+    /// within the debounce time.
+    Removed(PathBuf),
+    /// The file was renamed (from, to).
+    Renamed(PathBuf, PathBuf),
+    /// Events may have been lost so all tracked
+    /// libraries have to be checked.
+    Rescan,
+    /// The backend failed.
+    Error(Error),
+}
+
+// Size and modification time of a file. Used to
+// tell if a file is still being written to.
+type FileStat = Option<(u64, SystemTime)>;
+
+#[derive(Debug)]
+enum Pending {
+    Changed(FileStat),
+    Removed,
+    RenamedTo(PathBuf),
+}
+
+// Groups the raw events for a path until nothing
+// has happened to it for the debounce time and
+// the file has stopped growing. Closing a file
+// that was written to ends the wait early as
+// that's what compilers and linkers do once the
+// artifact is finished.
+#[derive(Debug)]
+pub(crate) struct Debouncer {
+    debounce: Duration,
+    pending: BTreeMap<PathBuf, (Pending, Instant)>,
+    ready: Vec<WatchEvent>,
+}
+
+impl Debouncer {
+    pub(crate) fn new(debounce: Duration) -> Debouncer {
+        Debouncer {
+            debounce,
+            pending: BTreeMap::new(),
+            ready: Vec::new(),
+        }
+    }
+
+    // Starts a thread that debounces the events
+    // sent to the returned sender and forwards
+    // them to ```tx```. The thread exits once the
+    // returned sender has been dropped.
+    pub(crate) fn spawn(debounce: Duration, tx: Sender<WatchEvent>) -> Sender<RawEvent> {
+        let (raw_tx, raw_rx) = channel();
+        thread::spawn(move || Debouncer::new(debounce).run(raw_rx, tx));
+        raw_tx
+    }
+
+    fn run(mut self, raw: Receiver<RawEvent>, tx: Sender<WatchEvent>) {
+        loop {
+            let evt = match self.next_deadline() {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    match raw.recv_timeout(timeout) {
+                        Ok(evt) => Some(evt),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                None => match raw.recv() {
+                    Ok(evt) => Some(evt),
+                    Err(_) => return,
+                },
+            };
+
+            let now = Instant::now();
+
+            if let Some(evt) = evt {
+                self.push(evt, now);
+            }
+
+            for evt in self.flush(now) {
+                if tx.send(evt).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn push(&mut self, evt: RawEvent, now: Instant) {
+        let deadline = now + self.debounce;
+
+        match evt {
+            RawEvent::Modified(path) => {
+                let stat = Self::stat(&path);
+                self.pending
+                    .insert(path, (Pending::Changed(stat), deadline));
+            }
+            RawEvent::WriteClosed(path) => {
+                let stat = Self::stat(&path);
+                self.pending.insert(path, (Pending::Changed(stat), now));
+            }
+            RawEvent::Removed(path) => {
+                self.pending.insert(path, (Pending::Removed, deadline));
+            }
+            RawEvent::Renamed(from, to) => {
+                self.pending
+                    .insert(from, (Pending::RenamedTo(to), deadline));
+            }
+            RawEvent::Rescan => self.ready.push(WatchEvent::Rescan),
+            RawEvent::Error(e) => self.ready.push(WatchEvent::Error(e)),
+        }
+    }
+
+    // Returns the events that are ready at ```now```
+    pub(crate) fn flush(&mut self, now: Instant) -> Vec<WatchEvent> {
+        let expired: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        for path in expired {
+            let (pending, _) = self.pending.remove(&path).unwrap();
+
+            match pending {
+                Pending::Changed(stat) => {
+                    let current = Self::stat(&path);
+
+                    // Still being written, wait some more
+                    if current.is_some() && current != stat {
+                        let deadline = now + self.debounce;
+                        self.pending
+                            .insert(path, (Pending::Changed(current), deadline));
+                    } else {
+                        self.ready.push(WatchEvent::Changed(path));
+                    }
+                }
+                // Replaced by a new file
+                Pending::Removed if path.exists() => {
+                    self.ready.push(WatchEvent::Changed(path));
+                }
+                Pending::Removed => self.ready.push(WatchEvent::Removed(path)),
+                Pending::RenamedTo(to) => self.ready.push(WatchEvent::Renamed(path, to)),
+            }
+        }
+
+        std::mem::take(&mut self.ready)
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|(_, deadline)| *deadline).min()
+    }
+
+    fn stat(path: &Path) -> FileStat {
+        let metadata = fs::metadata(path).ok()?;
+        Some((metadata.len(), metadata.modified().ok()?))
+    }
+}
@@ -0,0 +1,391 @@
+use crate::{Error, Result};
+use std::{collections::HashSet, fs, os::unix::fs::FileExt, path::Path};
+
+const MAGIC: &[u8] = b"\x7fELF";
+
+const ET_DYN: u64 = 3;
+const PT_LOAD: u64 = 1;
+const PT_DYNAMIC: u64 = 2;
+const SHT_DYNSYM: u64 = 11;
+const SHT_NOBITS: u64 = 8;
+const SHN_UNDEF: u64 = 0;
+const DT_NULL: u64 = 0;
+
+/// Machine the host was built for as
+/// (```e_machine```, name). ```None``` on
+/// architectures not listed here, which skips the
+/// check.
+const HOST_MACHINE: Option<(u64, &str)> = if cfg!(target_arch = "x86_64") {
+    Some((62, "x86_64"))
+} else if cfg!(target_arch = "x86") {
+    Some((3, "x86"))
+} else if cfg!(target_arch = "aarch64") {
+    Some((183, "aarch64"))
+} else if cfg!(target_arch = "arm") {
+    Some((40, "arm"))
+} else if cfg!(target_arch = "riscv64") {
+    Some((243, "riscv64"))
+} else if cfg!(target_arch = "powerpc64") {
+    Some((21, "powerpc64"))
+} else if cfg!(target_arch = "s390x") {
+    Some((22, "s390x"))
+} else if cfg!(target_arch = "loongarch64") {
+    Some((258, "loongarch64"))
+} else {
+    None
+};
+
+/// Reads the ELF file at ```path``` and checks
+/// that it can be loaded by the host. Errors name
+/// ```name```, the file the user knows about when
+/// ```path``` is a shadow copy of it.
+pub(crate) fn check(path: &Path, name: &Path) -> Result<()> {
+    Elf::open(path, name)?.check_contents()
+}
+
+/// Names of the symbols the ELF file at
+/// ```path``` exports through its dynamic symbol
+/// table. Errors name ```name``` like in
+/// ```check```.
+pub(crate) fn exports(path: &Path, name: &Path) -> Result<HashSet<String>> {
+    Elf::open(path, name)?.dynamic_symbols()
+}
+
+/// Headers of an ELF file. Only the parts that
+/// are looked at are read, every read is bounds
+/// checked against the length of the file so a
+/// cut off file is reported as
+/// ```Error::Truncated```.
+pub(crate) struct Elf<'a> {
+    file: fs::File,
+    len: u64,
+    path: &'a Path,
+    header: Vec<u8>,
+    wide: bool,
+    little: bool,
+}
+
+impl<'a> Elf<'a> {
+    /// Opens the file at ```path``` and checks the
+    /// class, byte order, machine and type against
+    /// the host. Errors name ```name```.
+    pub(crate) fn open(path: &Path, name: &'a Path) -> Result<Elf<'a>> {
+        let read_err = |e| Error::Read(e, name.to_path_buf());
+        let file = fs::File::open(path).map_err(read_err)?;
+        let len = file.metadata().map_err(read_err)?.len();
+
+        let mut ident = vec![0; len.min(16) as usize];
+        file.read_exact_at(&mut ident, 0).map_err(read_err)?;
+
+        Self::parse(file, len, &ident, name)
+    }
+
+    fn parse(file: fs::File, len: u64, data: &[u8], path: &'a Path) -> Result<Elf<'a>> {
+        if data.len() < 16 {
+            return match data.starts_with(&MAGIC[..data.len().min(4)]) {
+                true => Err(Error::Truncated(path.to_path_buf())),
+                false => Err(Error::InvalidElf(
+                    "no ELF header".to_string(),
+                    path.to_path_buf(),
+                )),
+            };
+        }
+
+        if !data.starts_with(MAGIC) {
+            return Err(Error::InvalidElf(
+                "no ELF header".to_string(),
+                path.to_path_buf(),
+            ));
+        }
+
+        let wide = match data[4] {
+            1 => false,
+            2 => true,
+            class => {
+                let reason = format!("unknown class {}", class);
+                return Err(Error::InvalidElf(reason, path.to_path_buf()));
+            }
+        };
+
+        let little = match data[5] {
+            1 => true,
+            2 => false,
+            order => {
+                let reason = format!("unknown byte order {}", order);
+                return Err(Error::InvalidElf(reason, path.to_path_buf()));
+            }
+        };
+
+        let mut elf = Elf {
+            file,
+            len,
+            path,
+            header: Vec::new(),
+            wide,
+            little,
+        };
+
+        // Reads the whole header so a file cut off
+        // inside it is reported first
+        elf.header = elf.read(0, if wide { 64 } else { 52 })?;
+
+        let machine = elf.field(&elf.header, 18, 2)?;
+        let host_wide = cfg!(target_pointer_width = "64");
+        let host_little = cfg!(target_endian = "little");
+        let host_machine = HOST_MACHINE.map_or(machine, |(id, _)| id);
+
+        if wide != host_wide || little != host_little || machine != host_machine {
+            return Err(Error::WrongArchitecture(
+                Self::describe(host_wide, host_little, HOST_MACHINE.map(|(_, name)| name)),
+                Self::describe(wide, little, Self::machine_name(machine)),
+                path.to_path_buf(),
+            ));
+        }
+
+        if elf.field(&elf.header, 16, 2)? != ET_DYN {
+            return Err(Error::NotSharedLibrary(path.to_path_buf()));
+        }
+
+        Ok(elf)
+    }
+
+    /// Checks that the segments, sections and the
+    /// dynamic section are all in the file.
+    pub(crate) fn check_contents(&self) -> Result<()> {
+        let mut dynamic = None;
+
+        for segment in self.segments()? {
+            if segment.kind == PT_LOAD || segment.kind == PT_DYNAMIC {
+                self.check_range(segment.offset, segment.size)?;
+            }
+
+            if segment.kind == PT_DYNAMIC {
+                dynamic = Some(segment);
+            }
+        }
+
+        for section in self.sections()? {
+            if section.kind != SHT_NOBITS {
+                self.check_range(section.offset, section.size)?;
+            }
+        }
+
+        let dynamic = match dynamic {
+            Some(dynamic) => dynamic,
+            None => {
+                let reason = "no dynamic section".to_string();
+                return Err(Error::InvalidElf(reason, self.path.to_path_buf()));
+            }
+        };
+
+        // The dynamic section ends with DT_NULL
+        let entry_size = if self.wide { 16 } else { 8 };
+        let entries = self.table(dynamic.offset, entry_size, dynamic.size / entry_size)?;
+
+        match entries
+            .iter()
+            .any(|entry| matches!(self.word(entry, 0, 0), Ok(DT_NULL)))
+        {
+            true => Ok(()),
+            false => Err(Error::Truncated(self.path.to_path_buf())),
+        }
+    }
+
+    /// Names of the symbols defined in the dynamic
+    /// symbol table
+    pub(crate) fn dynamic_symbols(&self) -> Result<HashSet<String>> {
+        let sections = self.sections()?;
+
+        let symbols = match sections.iter().find(|section| section.kind == SHT_DYNSYM) {
+            Some(symbols) => symbols,
+            None => return Ok(HashSet::new()),
+        };
+
+        let strings = match sections.get(symbols.link as usize) {
+            Some(strings) => self.read(strings.offset, strings.size)?,
+            None => {
+                let reason = "no dynamic string table".to_string();
+                return Err(Error::InvalidElf(reason, self.path.to_path_buf()));
+            }
+        };
+
+        let entry_size = match symbols.entry_size {
+            0 if self.wide => 24,
+            0 => 16,
+            size => size,
+        };
+
+        let index_at = if self.wide { 6 } else { 14 };
+        let mut names = HashSet::new();
+
+        for entry in self.table(symbols.offset, entry_size, symbols.size / entry_size)? {
+            let name = self.field(&entry, 0, 4)? as usize;
+
+            if name == 0 || self.field(&entry, index_at, 2)? == SHN_UNDEF {
+                continue;
+            }
+
+            let name = match strings.get(name..) {
+                Some(rest) => rest.split(|b| *b == 0).next().unwrap_or_default(),
+                None => return Err(Error::Truncated(self.path.to_path_buf())),
+            };
+
+            names.insert(String::from_utf8_lossy(name).into_owned());
+        }
+
+        Ok(names)
+    }
+
+    /// Program headers of the file
+    fn segments(&self) -> Result<Vec<Segment>> {
+        let (size_at, count_at) = if self.wide { (54, 56) } else { (42, 44) };
+        let table = self.table(
+            self.word(&self.header, 32, 28)?,
+            self.field(&self.header, size_at, 2)?,
+            self.field(&self.header, count_at, 2)?,
+        )?;
+
+        table
+            .iter()
+            .map(|entry| {
+                Ok(Segment {
+                    kind: self.field(entry, 0, 4)?,
+                    offset: self.word(entry, 8, 4)?,
+                    size: self.word(entry, 32, 16)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Section headers of the file
+    fn sections(&self) -> Result<Vec<Section>> {
+        let (size_at, count_at) = if self.wide { (58, 60) } else { (46, 48) };
+        let table = self.table(
+            self.word(&self.header, 40, 32)?,
+            self.field(&self.header, size_at, 2)?,
+            self.field(&self.header, count_at, 2)?,
+        )?;
+
+        table
+            .iter()
+            .map(|entry| {
+                Ok(Section {
+                    kind: self.field(entry, 4, 4)?,
+                    offset: self.word(entry, 24, 16)?,
+                    size: self.word(entry, 32, 20)?,
+                    link: self.field(entry, if self.wide { 40 } else { 24 }, 4)?,
+                    entry_size: self.word(entry, 56, 36)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Reads the ```count``` entries of a table in
+    /// one go, after checking that all of them are
+    /// in the file
+    fn table(&self, offset: u64, entry_size: u64, count: u64) -> Result<Vec<Vec<u8>>> {
+        let size = entry_size
+            .checked_mul(count)
+            .ok_or_else(|| Error::Truncated(self.path.to_path_buf()))?;
+
+        let data = self.read(offset, size)?;
+        let entry_size = entry_size as usize;
+
+        Ok((0..count as usize)
+            .map(|i| data[i * entry_size..(i + 1) * entry_size].to_vec())
+            .collect())
+    }
+
+    /// Address sized value at ```wide_offset``` or
+    /// ```narrow_offset``` in ```entry``` depending
+    /// on the class of the file
+    fn word(&self, entry: &[u8], wide_offset: usize, narrow_offset: usize) -> Result<u64> {
+        match self.wide {
+            true => self.field(entry, wide_offset, 8),
+            false => self.field(entry, narrow_offset, 4),
+        }
+    }
+
+    /// Unsigned value of ```size``` bytes at
+    /// ```offset``` in a table entry
+    fn field(&self, entry: &[u8], offset: usize, size: usize) -> Result<u64> {
+        match entry.get(offset..offset + size) {
+            Some(bytes) => Ok(self.decode(bytes)),
+            None => {
+                let reason = format!("table entries of {} bytes", entry.len());
+                Err(Error::InvalidElf(reason, self.path.to_path_buf()))
+            }
+        }
+    }
+
+    /// Unsigned value stored in ```bytes``` in the
+    /// byte order of the file
+    fn decode(&self, bytes: &[u8]) -> u64 {
+        let fold = |value: u64, byte: &u8| value << 8 | *byte as u64;
+
+        match self.little {
+            true => bytes.iter().rev().fold(0, fold),
+            false => bytes.iter().fold(0, fold),
+        }
+    }
+
+    fn read(&self, offset: u64, size: u64) -> Result<Vec<u8>> {
+        self.check_range(offset, size)?;
+
+        let mut data = vec![0; size as usize];
+        self.file
+            .read_exact_at(&mut data, offset)
+            .map_err(|e| Error::Read(e, self.path.to_path_buf()))?;
+        Ok(data)
+    }
+
+    fn check_range(&self, offset: u64, size: u64) -> Result<()> {
+        match offset.checked_add(size) {
+            Some(end) if end <= self.len => Ok(()),
+            _ => Err(Error::Truncated(self.path.to_path_buf())),
+        }
+    }
+
+    fn describe(wide: bool, little: bool, machine: Option<&str>) -> String {
+        format!(
+            "{}-bit {} endian {}",
+            if wide { 64 } else { 32 },
+            if little { "little" } else { "big" },
+            machine.unwrap_or("unknown machine")
+        )
+    }
+
+    fn machine_name(machine: u64) -> Option<&'static str> {
+        match machine {
+            3 => Some("x86"),
+            8 => Some("mips"),
+            20 => Some("powerpc"),
+            21 => Some("powerpc64"),
+            22 => Some("s390x"),
+            40 => Some("arm"),
+            62 => Some("x86_64"),
+            183 => Some("aarch64"),
+            243 => Some("riscv"),
+            258 => Some("loongarch"),
+            _ => None,
+        }
+    }
+}
+
+/// Program header of an ELF file
+#[derive(Debug)]
+struct Segment {
+    kind: u64,
+    offset: u64,
+    size: u64,
+}
+
+/// Section header of an ELF file
+#[derive(Debug)]
+struct Section {
+    kind: u64,
+    offset: u64,
+    size: u64,
+    link: u64,
+    entry_size: u64,
+}
@@ -15,6 +15,44 @@ pub enum Error {
     CopyTimeOut(PathBuf, PathBuf),
     /// Failed to find library
     Find(String),
+    /// The file watcher failed
+    Watcher(notify::Error),
+    /// Failed to create the shadow directory
+    ShadowDir(io::Error, PathBuf),
+    /// Failed to read a library
+    Read(io::Error, PathBuf),
+    /// Failed to look up a symbol
+    Symbol(libloading::Error, String),
+    /// The library of a symbol has been removed
+    Removed(String),
+    /// The library can only be reloaded when a
+    /// shadow directory is used
+    ShadowDirRequired(PathBuf),
+    /// The file name pattern is invalid
+    Pattern(glob::PatternError, String),
+    /// ```dr_plugin_init``` of a library returned
+    /// an error code
+    PluginInit(i32, PathBuf),
+    /// The vtable of a plugin was built with a
+    /// different layout (expected, found)
+    PluginLayout(u64, u64, PathBuf),
+    /// The library was built with another rustc
+    /// version, target, profile or API version
+    /// than the host
+    Incompatible(String, PathBuf),
+    /// The library is cut off
+    Truncated(PathBuf),
+    /// The library was built for another
+    /// architecture (expected, found)
+    WrongArchitecture(String, String, PathBuf),
+    /// The file is an executable or object file
+    /// instead of a shared library
+    NotSharedLibrary(PathBuf),
+    /// The file isn't a valid ELF file
+    InvalidElf(String, PathBuf),
+    /// The library doesn't export the required
+    /// symbols
+    MissingSymbols(Vec<String>),
 }
 
 impl StdError for Error {
@@ -24,6 +62,21 @@ impl StdError for Error {
             Error::Copy(_, _, _) => "Unable to copy",
             Error::CopyTimeOut(_, _) => "Unable to copy due to time out",
             Error::Find(_) => "Unable to find",
+            Error::Watcher(_) => "File watcher failed",
+            Error::ShadowDir(_, _) => "Unable to create shadow directory",
+            Error::Read(_, _) => "Unable to read",
+            Error::Symbol(_, _) => "Unable to find symbol",
+            Error::Removed(_) => "Library has been removed for symbol",
+            Error::ShadowDirRequired(_) => "A shadow directory is required to reload",
+            Error::Pattern(_, _) => "Invalid file name pattern",
+            Error::PluginInit(_, _) => "Plugin init failed",
+            Error::PluginLayout(_, _, _) => "Plugin vtable layout doesn't match",
+            Error::Incompatible(_, _) => "Library was built differently than the host",
+            Error::Truncated(_) => "Library is truncated",
+            Error::WrongArchitecture(_, _, _) => "Library was built for another architecture",
+            Error::NotSharedLibrary(_) => "Not a shared library",
+            Error::InvalidElf(_, _) => "Invalid ELF file",
+            Error::MissingSymbols(_) => "Library is missing required symbols",
         }
     }
 
@@ -34,6 +87,21 @@ impl StdError for Error {
             Error::Copy(ref e, _, _) => e.cause(),
             Error::CopyTimeOut(_, _) => None,
             Error::Find(_) => None,
+            Error::Watcher(ref e) => Some(e),
+            Error::ShadowDir(ref e, _) => Some(e),
+            Error::Read(ref e, _) => Some(e),
+            Error::Symbol(ref e, _) => Some(e),
+            Error::Removed(_) => None,
+            Error::ShadowDirRequired(_) => None,
+            Error::Pattern(ref e, _) => Some(e),
+            Error::PluginInit(_, _) => None,
+            Error::PluginLayout(_, _, _) => None,
+            Error::Incompatible(_, _) => None,
+            Error::Truncated(_) => None,
+            Error::WrongArchitecture(_, _, _) => None,
+            Error::NotSharedLibrary(_) => None,
+            Error::InvalidElf(_, _) => None,
+            Error::MissingSymbols(_) => None,
         }
     }
 }
@@ -62,6 +130,54 @@ impl fmt::Display for Error {
                 write!(fmt, "{} {:?} to {:?}", self.description(), src, dest)
             }
             Error::Find(ref name) => write!(fmt, "{} {}", self.description(), name),
+            Error::Watcher(ref e) => write!(fmt, "{}\nDue to: {}", self.description(), e),
+            Error::ShadowDir(ref e, ref dir) => {
+                write!(fmt, "{} in {:?}\nDue to: {}", self.description(), dir, e)
+            }
+            Error::Read(ref e, ref path) => {
+                write!(fmt, "{} {:?}\nDue to: {}", self.description(), path, e)
+            }
+            Error::Symbol(ref e, ref name) => {
+                write!(fmt, "{} {}\nDue to: {}", self.description(), name, e)
+            }
+            Error::Removed(ref name) => write!(fmt, "{} {}", self.description(), name),
+            Error::ShadowDirRequired(ref path) => {
+                write!(fmt, "{} {:?}", self.description(), path)
+            }
+            Error::Pattern(ref e, ref pattern) => {
+                write!(fmt, "{} {}\nDue to: {}", self.description(), pattern, e)
+            }
+            Error::PluginInit(code, ref path) => {
+                write!(fmt, "{} {:?} with code {}", self.description(), path, code)
+            }
+            Error::PluginLayout(expected, found, ref path) => write!(
+                fmt,
+                "{} {:?}, expected {:#x} found {:#x}",
+                self.description(),
+                path,
+                expected,
+                found
+            ),
+            Error::Incompatible(ref diffs, ref path) => {
+                write!(fmt, "{} {:?}: {}", self.description(), path, diffs)
+            }
+            Error::Truncated(ref path) | Error::NotSharedLibrary(ref path) => {
+                write!(fmt, "{} {:?}", self.description(), path)
+            }
+            Error::WrongArchitecture(ref expected, ref found, ref path) => write!(
+                fmt,
+                "{} {:?}, expected {} found {}",
+                self.description(),
+                path,
+                expected,
+                found
+            ),
+            Error::InvalidElf(ref reason, ref path) => {
+                write!(fmt, "{} {:?}: {}", self.description(), path, reason)
+            }
+            Error::MissingSymbols(ref names) => {
+                write!(fmt, "{} {}", self.description(), names.join(", "))
+            }
         }
     }
 }
@@ -0,0 +1,236 @@
+use crate::debouncer::WatchEvent;
+use crate::{DynamicReload, Lib, PendingReloads, UpdateState};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    task::AtomicWaker,
+    Stream,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        mpsc::{channel, Receiver, TryRecvError},
+        Arc,
+    },
+    task::{Context, Poll},
+    thread,
+};
+
+/// The blocking part of
+/// [apply_pending](struct.DynamicReload.html#method.apply_pending).
+/// Reloads the pending libraries and returns the
+/// instance.
+pub type BlockingWork = Box<dyn FnOnce() -> DynamicReload + Send>;
+
+/// A reload event sent to the stream returned by
+/// [events](struct.DynamicReload.html#method.events).
+/// Contains the same information as the
+/// arguments passed to the
+/// [update](struct.DynamicReload.html#method.update)
+/// callback.
+#[derive(Debug)]
+pub struct ReloadEvent {
+    /// What happened to the library.
+    pub state: UpdateState,
+
+    /// The library the event is for.
+    pub lib: Option<Arc<Lib>>,
+}
+
+/// Stream of
+/// [ReloadEvent](struct.ReloadEvent.html)s
+/// returned by
+/// [events](struct.DynamicReload.html#method.events).
+///
+/// The stream ends when the
+/// [DynamicReload](struct.DynamicReload.html)
+/// instance is dropped or when ```events``` is
+/// called again.
+#[derive(Debug)]
+pub struct ReloadEvents {
+    recv: UnboundedReceiver<ReloadEvent>,
+}
+
+impl Stream for ReloadEvents {
+    type Item = ReloadEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<ReloadEvent>> {
+        Pin::new(&mut self.recv).poll_next(cx)
+    }
+}
+
+// Wakes apply_pending when the watcher has sent
+// an event and holds the sender for the stream
+// handed out by events
+#[derive(Debug)]
+pub(crate) struct AsyncState {
+    waker: Arc<AtomicWaker>,
+    sender: Option<UnboundedSender<ReloadEvent>>,
+}
+
+impl AsyncState {
+    // The watcher sends to a std channel which
+    // can't wake a task, so forward the events on
+    // a separate thread and wake the task from
+    // there. The thread exits when the watcher is
+    // dropped.
+    pub(crate) fn bridge(watch_recv: Receiver<WatchEvent>) -> (Receiver<WatchEvent>, AsyncState) {
+        let (tx, rx) = channel();
+        let waker = Arc::new(AtomicWaker::new());
+        let thread_waker = waker.clone();
+
+        thread::spawn(move || {
+            for evt in watch_recv {
+                if tx.send(evt).is_err() {
+                    break;
+                }
+
+                thread_waker.wake();
+            }
+        });
+
+        let state = AsyncState {
+            waker,
+            sender: None,
+        };
+
+        (rx, state)
+    }
+}
+
+// Resolves to the next event from the watcher.
+// Never resolves if there is no watcher. Borrows
+// the receiver mutably as it isn't Sync, which
+// would make apply_pending not Send.
+struct NextEvent<'a> {
+    recv: &'a mut Receiver<WatchEvent>,
+    waker: &'a AtomicWaker,
+}
+
+impl<'a> NextEvent<'a> {
+    fn try_recv(&self) -> Poll<WatchEvent> {
+        match self.recv.try_recv() {
+            Ok(evt) => Poll::Ready(evt),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => Poll::Pending,
+        }
+    }
+}
+
+impl<'a> Future for NextEvent<'a> {
+    type Output = WatchEvent;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<WatchEvent> {
+        if let Poll::Ready(evt) = self.try_recv() {
+            return Poll::Ready(evt);
+        }
+
+        // Check again after registering so an event
+        // sent in between isn't missed
+        self.waker.register(cx.waker());
+        self.try_recv()
+    }
+}
+
+impl DynamicReload {
+    /// Returns a stream of the reload events
+    /// produced by
+    /// [apply_pending](struct.DynamicReload.html#method.apply_pending).
+    ///
+    /// Only one stream is active at a time.
+    /// Calling this again ends the previous
+    /// stream. Events from
+    /// [update](struct.DynamicReload.html#method.update)
+    /// are only sent to its callback.
+    ///
+    /// Requires the ```async``` feature.
+    pub fn events(&mut self) -> ReloadEvents {
+        let (tx, rx) = unbounded();
+        self.async_state.sender = Some(tx);
+        ReloadEvents { recv: rx }
+    }
+
+    /// Waits until the watcher reports a change
+    /// and then reloads the changed libraries
+    /// the same way as
+    /// [update](struct.DynamicReload.html#method.update).
+    /// The reload events are sent to the stream
+    /// returned by
+    /// [events](struct.DynamicReload.html#method.events).
+    ///
+    /// Requires the ```async``` feature.
+    ///
+    /// Only the waiting is done in the returned
+    /// future. Copying (which retries for up to a
+    /// second), loading and unloading the libraries
+    /// blocks, so that work is handed to
+    /// ```spawn_blocking``` together with the
+    /// instance, which the work returns once it's
+    /// done. Pass the blocking pool of the runtime
+    /// so the reloads never run on its workers.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut events = dr.events();
+    ///
+    /// tokio::spawn(async move {
+    ///     while let Some(event) = events.next().await {
+    ///         println!("{:?}", event.state);
+    ///     }
+    /// });
+    ///
+    /// loop {
+    ///     dr = unsafe {
+    ///         dr.apply_pending(|work| async {
+    ///             tokio::task::spawn_blocking(work).await.unwrap()
+    ///         })
+    ///         .await
+    ///     };
+    /// }
+    /// ```
+    ///
+    /// # Safety
+    /// Same as
+    /// [update](struct.DynamicReload.html#method.update)
+    pub async unsafe fn apply_pending<S, F>(mut self, spawn_blocking: S) -> DynamicReload
+    where
+        S: FnOnce(BlockingWork) -> F,
+        F: Future<Output = DynamicReload>,
+    {
+        let first = NextEvent {
+            recv: &mut self.watch_recv,
+            waker: &self.async_state.waker,
+        }
+        .await;
+
+        let mut pending = PendingReloads::default();
+        Self::queue_event(&self, first, &mut pending);
+
+        while let Ok(evt) = self.watch_recv.try_recv() {
+            Self::queue_event(&self, evt, &mut pending);
+        }
+
+        spawn_blocking(Box::new(move || {
+            let mut sender = self.async_state.sender.clone();
+            // The caller of apply_pending vouched
+            // for the libraries
+            unsafe { Self::reload_libs(&mut self, pending, &Self::send_event, &mut sender) };
+            self
+        }))
+        .await
+    }
+
+    fn send_event(
+        sender: &mut Option<UnboundedSender<ReloadEvent>>,
+        state: UpdateState,
+        lib: Option<&Arc<Lib>>,
+    ) {
+        if let Some(sender) = sender.as_ref() {
+            // The stream may have been dropped
+            let _ = sender.unbounded_send(ReloadEvent {
+                state,
+                lib: lib.cloned(),
+            });
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{Error, Lib, Result};
+use std::ffi::{c_char, CStr};
+
+const FINGERPRINT_SYMBOL: &[u8] = b"dr_build_fingerprint\0";
+
+const RUSTC: &str = concat!(env!("DR_RUSTC_VERSION"), "\0");
+const TARGET: &str = concat!(env!("DR_TARGET"), "\0");
+const PROFILE: &str = concat!(env!("DR_PROFILE"), "\0");
+
+/// Describes how a library was built. Exported
+/// as ```dr_build_fingerprint``` with
+/// [declare_fingerprint!](macro.declare_fingerprint.html)
+/// and compared with the host right after a
+/// library has been opened.
+///
+/// The library has to be opened to read the
+/// fingerprint, so its static initializers have
+/// already run when it's compared. A mismatching
+/// library is closed again before
+/// ```dr_plugin_init``` or any of its functions
+/// are called and before it replaces the loaded
+/// version.
+///
+/// The strings are NUL terminated and have to
+/// live as long as the library.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BuildFingerprint {
+    /// Output of ```rustc -V```
+    pub rustc: *const c_char,
+    /// Target triple
+    pub target: *const c_char,
+    /// Cargo profile (```debug``` or ```release```)
+    pub profile: *const c_char,
+    /// Version of the API between the host and
+    /// the library, see
+    /// [api_version](struct.DynamicReloadBuilder.html#method.api_version)
+    pub api_version: u32,
+}
+
+// Only points to static strings
+unsafe impl Sync for BuildFingerprint {}
+
+impl BuildFingerprint {
+    /// Fingerprint of the current build with the
+    /// given API version. The rustc version,
+    /// target and profile are the ones this crate
+    /// was compiled with.
+    pub const fn new(api_version: u32) -> BuildFingerprint {
+        BuildFingerprint {
+            rustc: RUSTC.as_ptr() as *const c_char,
+            target: TARGET.as_ptr() as *const c_char,
+            profile: PROFILE.as_ptr() as *const c_char,
+            api_version,
+        }
+    }
+}
+
+/// Exports the
+/// [BuildFingerprint](struct.BuildFingerprint.html)
+/// of a library so mismatching builds are closed
+/// again before they are used.
+///
+/// # Examples
+///
+/// ```ignore
+/// dynamic_reload::declare_fingerprint!(3);
+/// ```
+#[macro_export]
+macro_rules! declare_fingerprint {
+    ($api_version:expr) => {
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        pub static dr_build_fingerprint: $crate::BuildFingerprint =
+            $crate::BuildFingerprint::new($api_version);
+    };
+}
+
+/// Compares the fingerprint exported by ```lib```
+/// (if any) with the host. The API version is
+/// only compared if the host has one.
+pub(crate) unsafe fn check(lib: &Lib, api_version: Option<u32>) -> Result<()> {
+    let found = match lib.lib.get::<*const BuildFingerprint>(FINGERPRINT_SYMBOL) {
+        Ok(found) => **found,
+        Err(_) => return Ok(()),
+    };
+
+    match differences(&BuildFingerprint::new(0), &found, api_version) {
+        diffs if diffs.is_empty() => Ok(()),
+        diffs => Err(Error::Incompatible(
+            diffs.join(", "),
+            lib.loaded_path.clone(),
+        )),
+    }
+}
+
+/// Describes each field of ```found``` that
+/// doesn't match ```host```
+pub(crate) unsafe fn differences(
+    host: &BuildFingerprint,
+    found: &BuildFingerprint,
+    api_version: Option<u32>,
+) -> Vec<String> {
+    let fields = [
+        ("rustc", host.rustc, found.rustc),
+        ("target", host.target, found.target),
+        ("profile", host.profile, found.profile),
+    ];
+
+    let mut diffs = Vec::new();
+
+    for (name, host, found) in fields.iter() {
+        let host = CStr::from_ptr(*host).to_string_lossy();
+        let found = match found.is_null() {
+            true => "<missing>".into(),
+            false => CStr::from_ptr(*found).to_string_lossy(),
+        };
+
+        if host != found {
+            diffs.push(format!("{} is {} but the host has {}", name, found, host));
+        }
+    }
+
+    if let Some(expected) = api_version {
+        if found.api_version != expected {
+            diffs.push(format!(
+                "api version is {} but the host has {}",
+                found.api_version, expected
+            ));
+        }
+    }
+
+    diffs
+}
@@ -0,0 +1,87 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+// Number of calls in flight for one version of a
+// library
+#[derive(Debug, Default)]
+pub(crate) struct CallCounter {
+    calls: Mutex<usize>,
+    idle: Condvar,
+}
+
+impl CallCounter {
+    fn enter(&self) {
+        *self.calls.lock().unwrap() += 1;
+    }
+
+    fn leave(&self) {
+        let mut calls = self.calls.lock().unwrap();
+        *calls -= 1;
+
+        if *calls == 0 {
+            self.idle.notify_all();
+        }
+    }
+
+    // Returns false if there were still calls in
+    // flight when the timeout hit
+    pub(crate) fn wait_idle(&self, timeout: Duration) -> bool {
+        let calls = self.calls.lock().unwrap();
+        let (calls, _) = self
+            .idle
+            .wait_timeout_while(calls, timeout, |calls| *calls > 0)
+            .unwrap();
+        *calls == 0
+    }
+}
+
+/// Marks a call into a library as in flight.
+///
+/// Returned by [enter](struct.Lib.html#method.enter).
+/// While any guard for a version of a library is
+/// alive DynamicReload won't unload that version.
+/// Reloads and removals wait for the guards to be
+/// dropped, up to the
+/// [unload_timeout](struct.DynamicReloadBuilder.html#method.unload_timeout),
+/// and leak the library if they aren't.
+///
+/// The guard doesn't hold on to the
+/// ```Arc<Lib>```. A host that only keeps raw
+/// function pointers taken from the library can
+/// drop the ```Arc``` and rely on the guard to
+/// keep that version loaded while it calls them.
+///
+/// # Examples
+///
+/// ```ignore
+/// let (guard, fun) = {
+///     let lib = handle.current().unwrap();
+///     let fun: Symbol<extern "C" fn() -> i32> = unsafe { lib.lib.get(b"shared_fun\0")? };
+///     (lib.enter(), *fun)
+/// };
+///
+/// fun();
+/// drop(guard);
+/// ```
+#[derive(Debug)]
+#[must_use = "the call is only marked as in flight while the guard is alive"]
+pub struct CallGuard {
+    calls: Arc<CallCounter>,
+}
+
+impl CallGuard {
+    pub(crate) fn new(calls: &Arc<CallCounter>) -> CallGuard {
+        calls.enter();
+        CallGuard {
+            calls: calls.clone(),
+        }
+    }
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        self.calls.leave();
+    }
+}
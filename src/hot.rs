@@ -0,0 +1,123 @@
+use crate::{DynamicReload, Lib, LibHandle, PlatformName, Result};
+use arc_swap::ArcSwapOption;
+use std::sync::{Arc, OnceLock, Weak};
+
+/// Library behind a module annotated with
+/// [hot_module](attr.hot_module.html).
+///
+/// The macro creates one as ```HOT_LIB``` in the
+/// module. It has to be loaded with
+/// [load](struct.HotModule.html#method.load)
+/// before the functions of the module are
+/// called. After that the functions follow the
+/// reloads done by the ```DynamicReload```
+/// instance it was loaded into.
+#[derive(Debug)]
+pub struct HotModule {
+    name: &'static str,
+    handle: OnceLock<LibHandle>,
+}
+
+impl HotModule {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str) -> HotModule {
+        HotModule {
+            name,
+            handle: OnceLock::new(),
+        }
+    }
+
+    /// Name of the library, formatted with
+    /// ```PlatformName::Yes``` when it's loaded.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Adds the library to ```reload```. Does
+    /// nothing if it has already been loaded.
+    ///
+    /// # Safety
+    /// Same as
+    /// [add_library](struct.DynamicReload.html#method.add_library)
+    /// and the functions declared in the module
+    /// have to match the exports of the library.
+    pub unsafe fn load(&self, reload: &mut DynamicReload) -> Result<()> {
+        if self.handle.get().is_some() {
+            return Ok(());
+        }
+
+        let lib = reload.add_library(self.name, PlatformName::Yes)?;
+
+        // Can't fail as the library was just added
+        let _ = self.handle.set(reload.handle(&lib).unwrap());
+        Ok(())
+    }
+
+    /// Current version of the library or
+    /// ```None``` if it hasn't been loaded or has
+    /// been removed.
+    pub fn current(&self) -> Option<Arc<Lib>> {
+        self.handle.get().and_then(|handle| handle.current())
+    }
+
+    #[doc(hidden)]
+    pub fn lib(&self) -> Arc<Lib> {
+        match self.current() {
+            Some(lib) => lib,
+            None => panic!("hot module library {} isn't loaded", self.name),
+        }
+    }
+
+}
+
+/// Function of a hot module resolved in one
+/// version of its library. The macro creates one
+/// for every wrapper so the export is only looked
+/// up again after a reload.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct HotSymbol<T> {
+    // NUL terminated
+    name: &'static str,
+    // The version is only held weakly so the
+    // static doesn't keep it loaded
+    bound: ArcSwapOption<(Weak<Lib>, T)>,
+}
+
+impl<T: Copy> HotSymbol<T> {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str) -> HotSymbol<T> {
+        HotSymbol {
+            name,
+            bound: ArcSwapOption::const_empty(),
+        }
+    }
+
+    /// Returns the function from ```lib```. The
+    /// pointer is only valid while ```lib``` is
+    /// held.
+    #[doc(hidden)]
+    pub unsafe fn get(&self, lib: &Arc<Lib>) -> T {
+        // The weak reference keeps the allocation
+        // so another version can't show up at the
+        // same address
+        if let Some((bound, fun)) = self.bound.load().as_deref() {
+            if std::ptr::eq(bound.as_ptr(), Arc::as_ptr(lib)) {
+                return *fun;
+            }
+        }
+
+        let fun = match lib.lib.get::<T>(self.name.as_bytes()) {
+            Ok(fun) => *fun,
+            Err(e) => panic!(
+                "{} not found in {:?}: {}",
+                self.name.trim_end_matches('\0'),
+                lib.loaded_path,
+                e
+            ),
+        };
+
+        self.bound.store(Some(Arc::new((Arc::downgrade(lib), fun))));
+        fun
+    }
+}
@@ -47,12 +47,15 @@
 //!
 
 use libloading::Library;
-use notify::{RecommendedWatcher, Watcher};
 use std::{
-    env, fs,
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet},
+    env, fmt, fs,
+    hash::Hasher,
+    io::{self, Read},
     path::{Path, PathBuf},
     sync::{
-        mpsc::{channel, Receiver, Sender},
+        mpsc::{Receiver, Sender},
+        atomic::{AtomicU64, Ordering},
         Arc,
     },
     thread,
@@ -64,9 +67,51 @@ pub use libloading;
 use tempdir::TempDir;
 
 #[cfg(test)] mod tests;
-
+// Lets the tests use the macros that refer to
+// ::dynamic_reload
+#[cfg(test)] extern crate self as dynamic_reload;
+
+mod builder;
+mod debouncer;
+#[cfg(all(unix, not(target_vendor = "apple")))]
+mod elf;
 mod error;
+#[cfg(feature = "async")]
+mod events;
+mod fingerprint;
+mod guard;
+mod hot;
+mod lifecycle;
+mod plugin;
+mod plugin_decl;
+mod poll;
+mod required;
+mod resolver;
+mod shared;
+mod state;
+mod symbol;
+mod watcher;
+pub use self::builder::DynamicReloadBuilder;
 pub use self::error::Error;
+pub use self::fingerprint::BuildFingerprint;
+pub use self::guard::CallGuard;
+pub use self::hot::{HotModule, HotSymbol};
+pub use dynamic_reload_macros::hot_module;
+use self::guard::CallCounter;
+use self::lifecycle::HostContext;
+#[cfg(feature = "async")]
+pub use self::events::{BlockingWork, ReloadEvent, ReloadEvents};
+pub use self::required::RequiredSymbol;
+pub use self::resolver::LibraryResolver;
+pub use self::plugin::Plugin;
+pub use self::plugin_decl::{layout_hash, PluginDecl, PluginVTable};
+pub use self::shared::{LibHandle, SharedDynamicReload};
+pub use self::state::StateBuffer;
+pub use self::symbol::ReloadableSymbol;
+use self::debouncer::{Debouncer, WatchEvent};
+use self::symbol::LibSlot;
+use self::poll::PollBackend;
+use self::watcher::{FallbackBackend, NotifyBackend, WatchBackend};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -89,6 +134,71 @@ pub struct Lib {
     /// look for updates in case the library has
     /// been changed.
     pub original_path: Option<PathBuf>,
+
+    /// The symlink the library was found
+    /// through, if any. Reloads go through the
+    /// link so repointing it loads the new
+    /// target.
+    pub link_path: Option<PathBuf>,
+
+    /// Identity of the library. Stays the same
+    /// when the library is reloaded.
+    pub id: LibId,
+
+    /// Version of the library. Starts at 0 when
+    /// the library is added and is increased by
+    /// one for every reload.
+    pub generation: u64,
+
+    /// Hash of the content of the loaded file.
+    /// Used to skip reloads when the file on
+    /// disk hasn't actually changed.
+    pub content_hash: u64,
+
+    // Device and inode of the loaded file (Unix
+    // only)
+    file_id: Option<(u64, u64)>,
+
+    // Every link followed to get from link_path
+    // to original_path, see link_chain
+    links: Vec<PathBuf>,
+
+    // Kept open for libraries loaded without a
+    // shadow directory, see open_in_place. Declared
+    // after lib so it's closed once the library
+    // has been unloaded
+    _file: Option<fs::File>,
+
+    // Calls in flight, shared with the guards
+    // returned by enter
+    calls: Arc<CallCounter>,
+}
+
+impl Lib {
+    /// Marks the start of a call into this
+    /// version of the library. The version isn't
+    /// unloaded by a reload or removal until the
+    /// returned guard has been dropped (or the
+    /// [unload_timeout](struct.DynamicReloadBuilder.html#method.unload_timeout)
+    /// hits), even if every ```Arc<Lib>``` has
+    /// been dropped by then.
+    pub fn enter(&self) -> CallGuard {
+        CallGuard::new(&self.calls)
+    }
+}
+
+/// Unique identity of a library added with
+/// [add_library](struct.DynamicReload.html#method.add_library).
+/// All versions of a reloaded library share the
+/// same id.
+#[derive(PartialEq,Eq,PartialOrd,Ord,Hash,Debug,Clone,Copy)]
+pub struct LibId(u64);
+
+impl LibId {
+    fn next() -> LibId {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        LibId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
 }
 
 use derivative::Derivative;
@@ -99,27 +209,230 @@ use derivative::Derivative;
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct DynamicReload {
-    libs:          Vec<Arc<Lib>>,
+    libs:            Vec<TrackedLib>,
 
     #[derivative(Debug="ignore")]
-    watcher:       Option<RecommendedWatcher>,
-    shadow_dir:    Option<TempDir>,
-    search_paths:  Vec<PathBuf>,
-    watch_recv:    Receiver<notify::DebouncedEvent>,
+    watcher:         Option<Box<dyn WatchBackend>>,
+    shadow_dir:      Option<TempDir>,
+    search_paths:    Vec<PathBuf>,
+    watch_recv:      Receiver<WatchEvent>,
+    search:          Search,
+    unload:          UnloadPolicy,
+    timestamps:      bool,
+    path_match:      PathMatch,
+    unload_timeout:  Duration,
+    delete_policy:   DeletePolicy,
+    waiting:         Vec<WaitingLib>,
+    dirs:            Vec<PluginDir>,
+    host_context:    HostContext,
+    api_version:     Option<u32>,
+
+    #[cfg(feature = "async")]
+    async_state:     events::AsyncState,
+}
+
+// File events collected by one call to update
+#[derive(Debug, Default)]
+struct PendingReloads {
+    libs:    BTreeMap<LibId, PathBuf>,
+    removed: BTreeMap<LibId, Option<PathBuf>>,
+    added:   BTreeSet<PathBuf>,
+    errors:  Vec<Error>,
+}
+
+// A library tracked for reloading together with
+// the slot shared with the symbols handed out
+// for it.
+#[derive(Debug)]
+struct TrackedLib {
+    lib:        Arc<Lib>,
+    slot:       LibSlot,
+    // Found by add_directory, so it's unloaded
+    // when its file is removed
+    discovered: bool,
+    checks:     LibChecks,
+    // Already shut down, so removing it doesn't
+    // call dr_plugin_shutdown again
+    stopped:    bool,
+}
+
+impl TrackedLib {
+    fn new(lib: Arc<Lib>, slot: LibSlot) -> TrackedLib {
+        slot.store(Some(lib.clone()));
+        TrackedLib {
+            lib,
+            slot,
+            discovered: false,
+            checks: LibChecks::default(),
+            stopped: false,
+        }
+    }
+}
+
+// What every version of a library has to pass
+// before it's used
+#[derive(Debug, Clone, Default)]
+struct LibChecks {
+    // Layout hash every version has to export,
+    // see add_plugin
+    vtable_hash: Option<u64>,
+    // See add_library_with_symbols
+    required:    Vec<RequiredSymbol>,
+}
+
+impl LibChecks {
+    // Done on the file that is opened, the shadow
+    // copy if there is one, so a version that
+    // fails isn't even opened. Errors name
+    // ```shown```
+    fn before_load(&self, path: &Path, shown: &Path) -> Result<()> {
+        required::check_file(path, shown, &self.required)
+    }
+
+    unsafe fn after_load(&self, lib: &Lib) -> Result<()> {
+        required::check_loaded(lib, &self.required)?;
+
+        match self.vtable_hash {
+            Some(hash) => plugin::check_layout(lib, hash),
+            None => Ok(()),
+        }
+    }
+}
+
+// A directory added with add_directory
+#[derive(Debug)]
+struct PluginDir {
+    path:    PathBuf,
+    pattern: glob::Pattern,
+}
+
+// A library that was unloaded because its file
+// was removed and that is loaded again when the
+// file comes back. See DeletePolicy::Wait
+#[derive(Debug)]
+struct WaitingLib {
+    id:            LibId,
+    generation:    u64,
+    original_path: PathBuf,
+    links:         Vec<PathBuf>,
+    checks:        LibChecks,
+    slot:          LibSlot,
 }
 
 /// Searching for a shared library can be done in
 /// current directory, but can also be allowed to
-/// search backwards.
-#[derive(Debug)]
+/// search backwards or be handled by a custom
+/// [LibraryResolver](trait.LibraryResolver.html).
+#[derive(Clone)]
 pub enum Search {
 
-    /// Search in current directory only
+    /// Search in current directory, the search
+    /// paths and the directory of the
+    /// executable only
     Default,
 
     /// Allow searching in current directory and
     /// backwards of parent directories as well
     Backwards,
+
+    /// Let a resolver decide where libraries are
+    /// located. No other locations are searched.
+    Custom(Arc<dyn LibraryResolver>),
+}
+
+impl Search {
+    /// Creates a ```Search::Custom``` from a
+    /// resolver.
+    pub fn custom<R: LibraryResolver + 'static>(resolver: R) -> Search {
+        Search::Custom(Arc::new(resolver))
+    }
+}
+
+impl fmt::Debug for Search {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Search::Default => write!(f, "Default"),
+            Search::Backwards => write!(f, "Backwards"),
+            Search::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// Decides what happens to the old version of
+/// a library when it's replaced by a reload.
+#[derive(PartialEq,Debug,Clone,Copy)]
+pub enum UnloadPolicy {
+
+    /// Unload the old library once the last
+    /// reference to it has been dropped.
+    Unload,
+
+    /// Never unload old libraries. Useful for
+    /// libraries that can't be safely unloaded
+    /// (thread locals with destructors, etc)
+    Keep,
+}
+
+/// Decides which file events cause a library to
+/// be reloaded.
+#[derive(PartialEq,Debug,Clone,Copy)]
+pub enum PathMatch {
+
+    /// Only reload when the changed file is the
+    /// file the library was loaded from. Paths
+    /// are compared after being canonicalized and
+    /// on Unix also by device and inode.
+    Exact,
+
+    /// Reload when the changed file has the same
+    /// file name as the library, regardless of
+    /// which directory it's in. This was the
+    /// behavior before ```Exact``` was added.
+    FileName,
+}
+
+/// Decides what happens to a library when its
+/// file is removed or renamed.
+#[derive(PartialEq,Debug,Clone,Copy)]
+pub enum DeletePolicy {
+
+    /// Keep running the loaded version. The
+    /// library is reloaded if the file comes
+    /// back.
+    Keep,
+
+    /// Unload the library and stop tracking it,
+    /// the same as calling
+    /// [remove_library](struct.DynamicReload.html#method.remove_library).
+    Unload,
+
+    /// Unload the library but keep watching for
+    /// the file. When it comes back the library
+    /// is loaded again and ```After``` is sent.
+    /// Handles and symbols pick up the new
+    /// version.
+    Wait,
+}
+
+/// How changes to libraries are detected.
+#[derive(PartialEq,Debug,Clone,Copy)]
+pub enum WatchMode {
+
+    /// Use the native file watcher of the
+    /// platform. Directories it can't watch (or
+    /// all of them if it isn't available) are
+    /// polled instead.
+    Auto,
+
+    /// Only use the native file watcher.
+    Native,
+
+    /// Always poll the directories of the
+    /// libraries for changes. Slower to react
+    /// but works on network file systems, bind
+    /// mounts, etc where the native watcher
+    /// doesn't report changes.
+    Poll,
 }
 
 /// This is the states that the callback function
@@ -143,7 +456,101 @@ pub enum UpdateState {
     /// (broken file, etc) this will be set and
     /// allow the application to to deal with the
     /// issue.
+    ///
+    /// The old version of the library is still
+    /// active and is the one passed to the
+    /// callback, so anything released on
+    /// ```Before``` can be restored from it.
+    /// No library is passed when there is no old
+    /// version, which is the case for libraries
+    /// found by
+    /// [add_directory](struct.DynamicReload.html#method.add_directory)
+    /// and for libraries waiting for their file
+    /// (see ```DeletePolicy::Wait```).
+    ///
+    /// If the new version fails its
+    /// ```dr_plugin_init``` and the old one then
+    /// fails to start again, neither version is
+    /// usable. Both errors are sent without a
+    /// library, the new version's first, and the
+    /// library is no longer tracked.
     ReloadFailed(Error),
+
+    /// The old library saved its state with a
+    /// schema version (first value) that the new
+    /// library doesn't understand (second value)
+    /// so the state wasn't restored. Sent before
+    /// ```After```. See
+    /// [StateBuffer](struct.StateBuffer.html)
+    StateMismatch(u32, u32),
+
+    /// The library was written to but the content
+    /// is the same as the loaded version (cargo
+    /// rewriting a fresh artifact, ```touch```,
+    /// etc) so no reload was done. ```Before```
+    /// isn't sent in this case.
+    Unchanged,
+
+    /// The old version of the library still had
+    /// calls in flight (see
+    /// [enter](struct.Lib.html#method.enter))
+    /// when the unload timeout hit. It has been
+    /// leaked instead of unloaded and is the one
+    /// passed to the callback. Sent after
+    /// ```After```.
+    UnloadTimedOut,
+
+    /// The file watcher reported an error and
+    /// changes may have been missed. No library is
+    /// passed to the callback.
+    WatchError(Error),
+
+    /// The file of the library was removed. What
+    /// happens next depends on the
+    /// [DeletePolicy](enum.DeletePolicy.html),
+    /// the library passed to the callback is the
+    /// loaded version. Libraries loaded by
+    /// [add_directory](struct.DynamicReload.html#method.add_directory)
+    /// are always unloaded.
+    Removed,
+
+    /// The file of the library was renamed to
+    /// the given path. Handled like
+    /// ```Removed``` as there is no longer a file
+    /// where the library was loaded from.
+    Renamed(PathBuf),
+
+    /// A new library showed up in a directory
+    /// added with
+    /// [add_directory](struct.DynamicReload.html#method.add_directory)
+    /// and has been loaded.
+    Added,
+}
+
+/// Returned when removing a library with
+/// [remove_library](struct.DynamicReload.html#method.remove_library)
+/// to tell if the library was unloaded.
+#[derive(PartialEq,Debug,Clone,Copy)]
+pub enum RemoveState {
+
+    /// No references to the library are left so
+    /// it has been unloaded.
+    Unloaded,
+
+    /// The library is no longer tracked but
+    /// still held by this many references
+    /// outside of DynamicReload. It will be
+    /// unloaded once they are dropped.
+    StillReferenced(usize),
+
+    /// The library is kept loaded because
+    /// ```UnloadPolicy::Keep``` is used.
+    Kept,
+
+    /// The library still had calls in flight
+    /// when the unload timeout hit so it has
+    /// been leaked instead of unloaded.
+    Leaked,
 }
 
 /// This is used to decide how the name used for
@@ -168,6 +575,29 @@ pub enum PlatformName {
 }
 
 impl<'a> DynamicReload {
+    ///
+    /// Creates a
+    /// [DynamicReloadBuilder](struct.DynamicReloadBuilder.html)
+    /// used to configure search paths, shadow
+    /// directory, search mode, debounce duration,
+    /// unload policy and timestamp naming.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // No extra search paths, temp directory
+    /// // in target/debug, allow search backwards
+    /// let dr = DynamicReload::builder()
+    ///     .shadow_dir("target/debug")
+    ///     .search(Search::Backwards)
+    ///     .debounce(Duration::from_secs(2))
+    ///     .build()?;
+    /// ```
+    ///
+    pub fn builder() -> DynamicReloadBuilder {
+        DynamicReloadBuilder::default()
+    }
+
     ///
     /// Creates a DynamicReload object.
     ///
@@ -180,41 +610,24 @@ impl<'a> DynamicReload {
     /// ```shadow_dir``` is a location where
     /// a temporary directory will be created to
     /// keep a copy of all the shared libraries
-    /// and load from there. The reason is that
-    /// some operating systems locks loaded shared
-    /// files which would make it impossible to
-    /// update them. By having a separate
-    /// directory DynamicReload will look for
-    /// changes in the original path while having
-    /// them loaded from another
+    /// and load from there.
     ///
     /// ```search``` This is to allow
     /// DynamicReload to search in parent
-    /// directiors from the executable. Set this
-    /// to ```Search::Backwards``` to allow that
-    /// or to ```Search::Default``` to only allow
-    /// seach in the currenty directory of the of
-    /// the executable
+    /// directiors from the executable.
     ///
     /// ```debounce_duration``` is the duration
     /// that the watcher will wait after the
     /// dynamic library changed on disk, until it
-    /// will cause a reload. (Multiple write calls
-    /// could be made to the library until it is
-    /// fully written.)
+    /// will cause a reload.
     ///
-    /// # Examples
+    /// Failing to create the file watcher or the
+    /// shadow directory is only printed and will
+    /// silently disable reloading. Use
+    /// [builder](struct.DynamicReload.html#method.builder)
+    /// to get these errors reported.
     ///
-    /// ```ignore
-    /// // No extra search paths, temp directory
-    /// // in target/debug, allow search backwards
-    /// DynamicReload::new(
-    ///     None, 
-    ///     Some("target/debug"), 
-    ///     Search::Backwards, 
-    ///     Duration::from_secs(2)
-    /// );
-    /// ```
+    /// # Examples
     ///
     /// ```ignore
     /// // "../.." extra search path, temp directory in target/debug, allow search backwards
@@ -224,17 +637,19 @@ impl<'a> DynamicReload {
     pub fn new(
         search_paths: Option<Vec<&'a str>>,
         shadow_dir: Option<&'a str>,
-        _search: Search,
+        search: Search,
         debounce_duration: Duration,
     ) -> DynamicReload {
-        let (tx, rx) = channel();
-        DynamicReload {
-            libs: Vec::new(),
-            watcher: Self::get_watcher(tx, debounce_duration),
-            shadow_dir: Self::get_temp_dir(shadow_dir),
-            watch_recv: rx,
-            search_paths: Self::get_search_paths(search_paths),
+        let mut builder = Self::builder()
+            .search_paths(search_paths.unwrap_or_default())
+            .search(search)
+            .debounce(debounce_duration);
+
+        if let Some(dir) = shadow_dir {
+            builder = builder.shadow_dir(dir);
         }
+
+        builder.build_lenient()
     }
 
     ///
@@ -266,9 +681,24 @@ impl<'a> DynamicReload {
     /// 3. Current directory of the executable
     ///
     /// 4. Search backwards from executable if
-    /// Backwards has been set DynamicReload::new
+    /// Backwards has been set on the builder
     ///
     /// ```
+    ///
+    /// If ```Search::Custom``` has been set only
+    /// the resolver is asked for the location.
+    ///
+    /// If the library is found through a symlink
+    /// (```current/libfoo.so -> v42/libfoo.so```)
+    /// the whole chain of links is followed and
+    /// the directories of the links and the
+    /// target are watched. Repointing a link
+    /// reloads the library from the new target.
+    ///
+    /// If the library exports
+    /// ```dr_plugin_init``` it's called before
+    /// this returns, see
+    /// [host_context](struct.DynamicReloadBuilder.html#method.host_context).
     /// # Examples
     ///
     /// ```ignore
@@ -302,30 +732,307 @@ impl<'a> DynamicReload {
         name: &str,
         name_format: PlatformName,
     ) -> Result<Arc<Lib>> {
-        match Self::try_load_library(self, name, name_format) {
+        match Self::try_load_library(self, name, name_format, &LibChecks::default()) {
             Ok(lib) => {
-                if let Some(w) = self.watcher.as_mut() {
-                    if let Some(path) = lib.original_path.as_ref() {
-                        let parent = path.as_path().parent().unwrap();
-                        let parent_buf = if cfg!(windows) {
-                            parent.to_path_buf().canonicalize().unwrap()
-                        } else {
-                            parent.to_path_buf()
-                        };
-
-                        let _ = w.watch(parent_buf, notify::RecursiveMode::NonRecursive);
-                    }
-                }
+                Self::watch_lib(self, &lib, &[]);
                 // Bump the ref here as we keep
                 // one around to keep track of
                 // files that needs to be reloaded
-                self.libs.push(lib.clone());
+                self.libs.push(TrackedLib::new(lib.clone(), LibSlot::default()));
                 Ok(lib)
             }
             Err(e) => Err(e),
         }
     }
 
+    ///
+    /// Same as
+    /// [add_library](struct.DynamicReload.html#method.add_library)
+    /// but the library has to export every symbol
+    /// in ```required```.
+    ///
+    /// The exports are checked before a version
+    /// is loaded (on ELF platforms by reading the
+    /// dynamic symbol table of the file that is
+    /// opened, the shadow copy if there is one,
+    /// so a build that changes in between can't
+    /// slip past the check). A library
+    /// missing any of them is refused with
+    /// ```Error::MissingSymbols```. On a reload
+    /// that's reported with
+    /// ```UpdateState::ReloadFailed``` while the
+    /// old version is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let lib = dr.add_library_with_symbols(
+    ///     "game_logic",
+    ///     PlatformName::Yes,
+    ///     ["update", "render:v2"],
+    /// )?;
+    /// ```
+    /// # Safety
+    /// Same as
+    /// [add_library](struct.DynamicReload.html#method.add_library)
+    pub unsafe fn add_library_with_symbols<I, S>(
+        &mut self,
+        name: &str,
+        name_format: PlatformName,
+        required: I,
+    ) -> Result<Arc<Lib>>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<RequiredSymbol>,
+    {
+        let checks = LibChecks {
+            required: required.into_iter().map(Into::into).collect(),
+            ..LibChecks::default()
+        };
+
+        let lib = Self::try_load_library(self, name, name_format, &checks)?;
+        Self::watch_lib(self, &lib, &[]);
+
+        self.libs.push(TrackedLib {
+            checks,
+            ..TrackedLib::new(lib.clone(), LibSlot::default())
+        });
+
+        Ok(lib)
+    }
+
+    ///
+    /// Same as
+    /// [add_library](struct.DynamicReload.html#method.add_library)
+    /// but for plugins that export a ```V``` with
+    /// [declare_plugin!](macro.declare_plugin.html).
+    ///
+    /// The library is refused with
+    /// ```Error::PluginLayout``` if it was built
+    /// against a different layout of ```V```. The
+    /// same check is done on every reload and a
+    /// version that fails it is reported with
+    /// ```UpdateState::ReloadFailed``` while the
+    /// old version is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut math = dr.add_plugin::<MathApi>("math", PlatformName::Yes)?;
+    /// println!("Value {}", (math.get()?.add)(1, 2));
+    /// ```
+    /// # Safety
+    /// Same as
+    /// [add_library](struct.DynamicReload.html#method.add_library)
+    pub unsafe fn add_plugin<V: PluginVTable>(
+        &mut self,
+        name: &str,
+        name_format: PlatformName,
+    ) -> Result<Plugin<V>> {
+        let checks = LibChecks {
+            vtable_hash: Some(V::LAYOUT_HASH),
+            ..LibChecks::default()
+        };
+
+        let lib = Self::try_load_library(self, name, name_format, &checks)?;
+        Self::watch_lib(self, &lib, &[]);
+
+        let tracked = TrackedLib {
+            checks,
+            ..TrackedLib::new(lib, LibSlot::default())
+        };
+
+        let slot = tracked.slot.clone();
+        self.libs.push(tracked);
+        Plugin::new(slot)
+    }
+
+    ///
+    /// Loads every shared library in ```path```
+    /// whose file name matches the glob
+    /// ```pattern``` and keeps watching the
+    /// directory (not recursive).
+    ///
+    /// Libraries created in the directory later
+    /// are loaded by
+    /// [update](struct.DynamicReload.html#method.update)
+    /// and reported with ```UpdateState::Added```.
+    /// Deleted ones are unloaded and reported
+    /// with ```UpdateState::Removed```. Changes to
+    /// the libraries are reloaded like for
+    /// [add_library](struct.DynamicReload.html#method.add_library).
+    ///
+    /// Returns the libraries that were loaded.
+    /// Fails without loading anything if the
+    /// pattern is invalid, the directory can't be
+    /// read or one of the libraries can't be
+    /// loaded.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // Loads libplugin_foo.so, libplugin_bar.so, etc
+    /// let plugins = dr.add_directory("plugins", "*plugin_*")?;
+    /// ```
+    /// # Safety
+    /// Same as
+    /// [add_library](struct.DynamicReload.html#method.add_library)
+    pub unsafe fn add_directory<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        pattern: &str,
+    ) -> Result<Vec<Arc<Lib>>> {
+        let path = path.as_ref();
+        let dir = PluginDir {
+            path: path.canonicalize().map_err(|e| Error::Read(e, path.to_path_buf()))?,
+            pattern: glob::Pattern::new(pattern)
+                .map_err(|e| Error::Pattern(e, pattern.to_string()))?,
+        };
+
+        let mut libs = Vec::new();
+
+        for file in Self::dir_libs(&dir)? {
+            if Self::is_tracked(self, &file) {
+                continue;
+            }
+
+            match Self::load_plugin(self, &file, LibId::next(), 0, &LibChecks::default()) {
+                Ok(lib) => libs.push(lib),
+                Err(err) => {
+                    for lib in libs.iter() {
+                        lifecycle::shutdown(lib);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        if let Some(w) = self.watcher.as_mut() {
+            let _ = w.watch(&dir.path);
+        }
+
+        self.dirs.push(dir);
+
+        for lib in libs.iter() {
+            Self::track_discovered(self, lib.clone());
+        }
+
+        Ok(libs)
+    }
+
+    ///
+    /// Stops tracking a library that has been
+    /// added with
+    /// [add_library](struct.DynamicReload.html#method.add_library)
+    ///
+    /// The parent directory of the library is no
+    /// longer watched unless another library
+    /// lives in it and the shadow copies of the
+    /// library are deleted. Copies of other
+    /// libraries with the same file name are
+    /// kept.
+    ///
+    /// ```lib``` can be any version of the
+    /// library, it's matched by its
+    /// [LibId](struct.LibId.html). If it's the
+    /// current version the reference passed in
+    /// isn't counted in
+    /// the returned
+    /// [RemoveState](enum.RemoveState.html) so
+    /// ```RemoveState::Unloaded``` means that the
+    /// library is unloaded once ```lib``` is
+    /// dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let lib = dr.add_library("test_shared", PlatformName::Yes)?;
+    /// // ...
+    /// match dr.remove_library(&lib)? {
+    ///     RemoveState::StillReferenced(count) => println!("{} references left", count),
+    ///     _ => (),
+    /// }
+    /// ```
+    ///
+    pub fn remove_library(&mut self, lib: &Arc<Lib>) -> Result<RemoveState> {
+        match self.libs.iter().position(|l| l.lib.id == lib.id) {
+            Some(index) => {
+                let borrowed = Arc::ptr_eq(&self.libs[index].lib, lib) as usize;
+                Ok(self.remove_library_at(index, borrowed))
+            }
+            None => Err(Error::Find(lib.loaded_path.to_string_lossy().into())),
+        }
+    }
+
+    ///
+    /// Same as
+    /// [remove_library](struct.DynamicReload.html#method.remove_library)
+    /// but finds the library from the name used
+    /// when calling
+    /// [add_library](struct.DynamicReload.html#method.add_library)
+    ///
+    pub fn remove_library_by_name(
+        &mut self,
+        name: &str,
+        name_format: PlatformName,
+    ) -> Result<RemoveState> {
+        let lib_name = Self::get_library_name(name, name_format);
+
+        let index = self.libs.iter().position(|TrackedLib { lib: l, .. }| {
+            let path = l.link_path.as_ref().or(l.original_path.as_ref());
+            let path = path.unwrap_or(&l.loaded_path);
+            path.file_name() == Some(lib_name.as_ref())
+        });
+
+        match index {
+            Some(index) => Ok(self.remove_library_at(index, 0)),
+            None => Err(Error::Find(lib_name)),
+        }
+    }
+
+    ///
+    /// Looks up ```name``` in ```lib``` and
+    /// returns a
+    /// [ReloadableSymbol](struct.ReloadableSymbol.html)
+    /// that is looked up again in the new version
+    /// of the library after it has been reloaded.
+    ///
+    /// Fails if ```lib``` isn't tracked by this
+    /// DynamicReload or if the symbol can't be
+    /// found.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut fun = dr.symbol::<extern "C" fn() -> i32>(&lib, "shared_fun")?;
+    /// println!("Value {}", (fun.get()?)());
+    /// ```
+    /// # Safety
+    /// ```T``` has to match the type of the
+    /// symbol in every version of the library.
+    /// Refer to the libloading documentation of
+    /// ```Library::get``` for details.
+    ///
+    pub unsafe fn symbol<T>(&self, lib: &Arc<Lib>, name: &str) -> Result<ReloadableSymbol<T>> {
+        match self.libs.iter().find(|l| l.lib.id == lib.id) {
+            Some(tracked) => ReloadableSymbol::new(name, tracked.slot.clone()),
+            None => Err(Error::Find(lib.loaded_path.to_string_lossy().into())),
+        }
+    }
+
+    ///
+    /// Returns a [LibHandle](struct.LibHandle.html)
+    /// that always points to the current version
+    /// of ```lib```. Returns ```None``` if ```lib```
+    /// isn't tracked by this DynamicReload.
+    ///
+    pub fn handle(&self, lib: &Arc<Lib>) -> Option<LibHandle> {
+        self.libs
+            .iter()
+            .find(|l| l.lib.id == lib.id)
+            .map(|l| LibHandle::new(l.lib.id, l.slot.clone()))
+    }
+
     /// Needs to be called in order to handle
     /// reloads of libraries.
     ///
@@ -335,6 +1042,31 @@ impl<'a> DynamicReload {
     /// depending on what needs to be done with
     /// the loaded library.
     ///
+    /// File events are handled like this:
+    ///
+    /// * A library file that was written to (or
+    ///   created) is reloaded.
+    /// * A file renamed over a library is handled
+    ///   like a write to the library.
+    /// * A removed library (or one renamed away)
+    ///   sends ```UpdateState::Removed``` (or
+    ///   ```Renamed```) and is then kept, unloaded
+    ///   or waits for the file to come back
+    ///   depending on the
+    ///   [DeletePolicy](enum.DeletePolicy.html).
+    ///   Removing a file and writing it again
+    ///   before ```update``` is called only
+    ///   reloads it.
+    /// * A new library in a directory added with
+    ///   ```add_directory``` is loaded and sends
+    ///   ```UpdateState::Added```.
+    /// * A rescan (the watcher may have lost
+    ///   events) checks the content of all
+    ///   libraries and reloads the ones that
+    ///   changed.
+    /// * Watcher errors are passed on as
+    ///   ```UpdateState::WatchError```.
+    ///
     /// ```ignore
     /// struct Plugins {
     ///     // ...
@@ -345,14 +1077,19 @@ impl<'a> DynamicReload {
     ///        match state {
     ///            UpdateState::Before => // save state, remove from lists, etc, here
     ///            UpdateState::After => // shared lib reloaded, re-add, restore state
-    ///            UpdateState::ReloadFailed(Error) => // shared lib failed to reload due to error
+    ///            UpdateState::ReloadFailed(Error) => // shared lib failed to reload due to error, old one still active
+    ///            UpdateState::StateMismatch(saved, expected) => // saved state wasn't restored
     ///        }
     ///    }
     /// }
     ///
     /// fn main() {
     ///     let plugins = Plugins { ... };
-    ///     let mut dr = DynamicReload::new(None, Some("target/debug"), Search::Backwards, Duration::from_secs(2));
+    ///     let mut dr = DynamicReload::builder()
+    ///         .shadow_dir("target/debug")
+    ///         .search(Search::Backwards)
+    ///         .build()
+    ///         .unwrap();
     ///     dr.add_library("test_shared", Search::Backwards);
     ///     dr.update(Plugin::reload_callback, &mut plugins);
     /// }
@@ -381,102 +1118,681 @@ impl<'a> DynamicReload {
     where
         F: Fn(&mut T, UpdateState, Option<&Arc<Lib>>),
     {
+        // A build usually triggers several events
+        // for the same file so group them to only
+        // reload each library once
+        let mut pending = PendingReloads::default();
+
         while let Ok(evt) = self.watch_recv.try_recv() {
-            use notify::DebouncedEvent::*;
-            match evt {
-                NoticeWrite(ref path) | Write(ref path) | Create(ref path) => {
-                    Self::reload_libs(self, path, update_call, data);
+            Self::queue_event(self, evt, &mut pending);
+        }
+
+        Self::reload_libs(self, pending, update_call, data);
+    }
+
+    fn queue_event(&self, evt: WatchEvent, pending: &mut PendingReloads) {
+        match evt {
+            WatchEvent::Changed(path) => {
+                Self::pending_added(self, &path, pending);
+                Self::pending_reloads(self, path, pending);
+            }
+            WatchEvent::Removed(path) => Self::pending_removals(self, &path, None, pending),
+            // A file renamed over a library (which is
+            // how many tools write files atomically)
+            // replaces it
+            WatchEvent::Renamed(from, to) => {
+                Self::pending_removals(self, &from, Some(&to), pending);
+                Self::pending_added(self, &to, pending);
+                Self::pending_reloads(self, to, pending);
+            }
+            WatchEvent::Rescan => Self::pending_rescan(self, pending),
+            WatchEvent::Error(e) => pending.errors.push(e),
+        }
+    }
+
+    fn pending_reloads(&self, file_path: PathBuf, pending: &mut PendingReloads) {
+        // Linked libraries are reloaded through the
+        // link so a repointed link is followed
+        for tracked in self.libs.iter() {
+            let lib = &tracked.lib;
+            if let Some(original) = lib.original_path.as_ref() {
+                if Self::matches_lib(self, &file_path, original, &lib.links) {
+                    let path = lib.link_path.clone().unwrap_or_else(|| file_path.clone());
+                    pending.libs.insert(lib.id, path);
+                }
+            }
+        }
+
+        for waiting in self.waiting.iter() {
+            if Self::matches_lib(self, &file_path, &waiting.original_path, &waiting.links) {
+                let path = waiting.links.first().cloned();
+                pending.libs.insert(waiting.id, path.unwrap_or_else(|| file_path.clone()));
+            }
+        }
+    }
+
+    fn pending_removals(
+        &self,
+        file_path: &Path,
+        renamed_to: Option<&PathBuf>,
+        pending: &mut PendingReloads,
+    ) {
+        for tracked in self.libs.iter() {
+            if let Some(original) = tracked.lib.original_path.as_ref() {
+                if Self::matches_lib(self, file_path, original, &tracked.lib.links) {
+                    pending.removed.insert(tracked.lib.id, renamed_to.cloned());
                 }
-                _ => (),
             }
         }
     }
 
-    unsafe fn reload_libs<F, T>(&mut self, file_path: &PathBuf, update_call: &F, data: &mut T)
+    // New libraries in the directories added with
+    // add_directory
+    fn pending_added(&self, file_path: &Path, pending: &mut PendingReloads) {
+        if Self::is_tracked(self, file_path) {
+            return;
+        }
+
+        if self.dirs.iter().any(|dir| Self::in_dir(dir, file_path)) {
+            pending.added.insert(file_path.to_path_buf());
+        }
+    }
+
+    // Events may have been lost so check the
+    // content of every tracked library and look for
+    // libraries that came or went in the
+    // directories added with add_directory
+    fn pending_rescan(&self, pending: &mut PendingReloads) {
+        for dir in self.dirs.iter() {
+            for file in Self::dir_libs(dir).unwrap_or_default() {
+                Self::pending_added(self, &file, pending);
+            }
+        }
+
+        for tracked in self.libs.iter().filter(|l| l.discovered) {
+            if let Some(path) = tracked.lib.original_path.as_ref() {
+                if !path.exists() {
+                    pending.removed.insert(tracked.lib.id, None);
+                }
+            }
+        }
+
+        for tracked in self.libs.iter() {
+            let lib = &tracked.lib;
+            if let Some(path) = lib.link_path.as_ref().or(lib.original_path.as_ref()) {
+                match Self::hash_file(path) {
+                    Ok(hash) if hash != tracked.lib.content_hash => {
+                        pending.libs.insert(tracked.lib.id, path.clone());
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    // Reloads in LibId order, that is in the
+    // order the libraries were added
+    unsafe fn reload_libs<F, T>(
+        &mut self,
+        pending: PendingReloads,
+        update_call: &F,
+        data: &mut T,
+    ) where
+        F: Fn(&mut T, UpdateState, Option<&Arc<Lib>>),
+    {
+        for err in pending.errors {
+            update_call(data, UpdateState::WatchError(err), None);
+        }
+
+        // A library that was removed and then
+        // written again is only reloaded
+        let removed: Vec<_> = pending
+            .removed
+            .into_iter()
+            .filter(|(id, _)| !pending.libs.contains_key(id))
+            .collect();
+
+        for (id, file_path) in pending.libs {
+            if let Some(index) = self.libs.iter().position(|l| l.lib.id == id) {
+                Self::reload_lib(self, index, &file_path, update_call, data);
+            } else if let Some(index) = self.waiting.iter().position(|l| l.id == id) {
+                Self::restore_lib(self, index, &file_path, update_call, data);
+            }
+        }
+
+        for (id, renamed_to) in removed {
+            if let Some(index) = self.libs.iter().position(|l| l.lib.id == id) {
+                Self::deleted_lib(self, index, renamed_to, update_call, data);
+            }
+        }
+
+        for file_path in pending.added {
+            // Several events may have led to the
+            // same library
+            if !Self::is_tracked(self, &file_path) {
+                Self::add_discovered(self, &file_path, update_call, data);
+            }
+        }
+    }
+
+    unsafe fn add_discovered<F, T>(&mut self, file_path: &Path, update_call: &F, data: &mut T)
     where
         F: Fn(&mut T, UpdateState, Option<&Arc<Lib>>),
     {
-        let len = self.libs.len();
-        for i in (0..len).rev() {
-            if Self::should_reload(file_path, &self.libs[i]) {
-                Self::reload_lib(self, i, file_path, update_call, data);
+        match Self::load_plugin(self, file_path, LibId::next(), 0, &LibChecks::default()) {
+            Ok(lib) => {
+                Self::track_discovered(self, lib.clone());
+                update_call(data, UpdateState::Added, Some(&lib));
+            }
+            Err(err) => update_call(data, UpdateState::ReloadFailed(err), None),
+        }
+    }
+
+    fn track_discovered(&mut self, lib: Arc<Lib>) {
+        let watched: Vec<PathBuf> = self.dirs.iter().map(|d| d.path.clone()).collect();
+        Self::watch_lib(self, &lib, &watched);
+
+        self.libs.push(TrackedLib {
+            discovered: true,
+            ..TrackedLib::new(lib, LibSlot::default())
+        });
+    }
+
+    // Loads a library that was unloaded by
+    // DeletePolicy::Wait once its file is back
+    unsafe fn restore_lib<F, T>(
+        &mut self,
+        index: usize,
+        file_path: &Path,
+        update_call: &F,
+        data: &mut T,
+    ) where
+        F: Fn(&mut T, UpdateState, Option<&Arc<Lib>>),
+    {
+        let waiting = &self.waiting[index];
+        let (id, generation) = (waiting.id, waiting.generation);
+
+        match Self::load_plugin(self, file_path, id, generation + 1, &waiting.checks) {
+            Ok(lib) => {
+                let waiting = self.waiting.swap_remove(index);
+                let dirs = Self::watch_dirs(Some(&waiting.original_path), &waiting.links);
+                self.libs.push(TrackedLib {
+                    checks: waiting.checks,
+                    ..TrackedLib::new(lib.clone(), waiting.slot)
+                });
+                Self::watch_lib(self, &lib, &dirs);
+                Self::unwatch_unused(self, dirs);
+                update_call(data, UpdateState::After, Some(&lib));
             }
+            // Keep waiting for a working version
+            Err(err) => update_call(data, UpdateState::ReloadFailed(err), None),
+        }
+    }
+
+    fn deleted_lib<F, T>(
+        &mut self,
+        index: usize,
+        renamed_to: Option<PathBuf>,
+        update_call: &F,
+        data: &mut T,
+    ) where
+        F: Fn(&mut T, UpdateState, Option<&Arc<Lib>>),
+    {
+        let lib = self.libs[index].lib.clone();
+
+        let policy = match self.libs[index].discovered {
+            true => DeletePolicy::Unload,
+            false => self.delete_policy,
+        };
+
+        let state = match renamed_to {
+            Some(to) => UpdateState::Renamed(to),
+            None => UpdateState::Removed,
+        };
+
+        update_call(data, state, Some(&lib));
+
+        let leaked = match (policy, lib.original_path.as_ref()) {
+            (DeletePolicy::Unload, _) => {
+                Self::remove_library_at(self, index, 1) == RemoveState::Leaked
+            }
+            (DeletePolicy::Wait, Some(original_path)) => {
+                self.waiting.push(WaitingLib {
+                    id: lib.id,
+                    generation: lib.generation,
+                    original_path: original_path.clone(),
+                    links: lib.links.clone(),
+                    checks: self.libs[index].checks.clone(),
+                    slot: self.libs[index].slot.clone(),
+                });
+                self.remove_lib(index)
+            }
+            _ => false,
+        };
+
+        if leaked {
+            update_call(data, UpdateState::UnloadTimedOut, Some(&lib));
         }
     }
 
     unsafe fn reload_lib<F, T>(
         &mut self,
         index: usize,
-        file_path: &PathBuf,
+        file_path: &Path,
         update_call: &F,
         data: &mut T,
     ) where
         F: Fn(&mut T, UpdateState, Option<&Arc<Lib>>),
     {
-        update_call(data, UpdateState::Before, Some(&self.libs[index]));
-        self.remove_lib(index);
+        let previous = self.libs[index].lib.clone();
+
+        // If the file can't be read yet let the
+        // reload deal with it
+        if let Ok(hash) = Self::hash_file(file_path) {
+            if hash == previous.content_hash {
+                update_call(data, UpdateState::Unchanged, Some(&previous));
+                return;
+            }
+        }
+
+        update_call(data, UpdateState::Before, Some(&previous));
 
-        match Self::load_library(self, file_path) {
+        // Load the new version before touching the
+        // old one so the old one stays active if
+        // the new one is broken
+        let checks = &self.libs[index].checks;
+        let result = Self::check_in_place(self, &previous, file_path)
+            .and_then(|_| {
+                Self::load_library(self, file_path, previous.id, previous.generation + 1, checks)
+            })
+            .and_then(|lib| checks.after_load(&lib).map(|_| lib));
+
+        match result {
             Ok(lib) => {
-                self.libs.push(lib.clone());
+                let saved_state = state::save_state(&previous);
+                lifecycle::shutdown(&previous);
+
+                let mismatch = saved_state.as_ref().and_then(|saved| {
+                    match state::load_state(&lib, saved) {
+                        Some(expected) if expected != saved.version => {
+                            Some(UpdateState::StateMismatch(saved.version, expected))
+                        }
+                        _ => None,
+                    }
+                });
+
+                // The new version only takes over once
+                // it has been initialized, otherwise the
+                // old one is started again
+                if let Err(err) = lifecycle::init(&lib, self.host_context) {
+                    drop(lib);
+
+                    if let Err(restart_err) = lifecycle::init(&previous, self.host_context) {
+                        update_call(data, UpdateState::ReloadFailed(err), None);
+                        update_call(data, UpdateState::ReloadFailed(restart_err), None);
+
+                        self.libs[index].stopped = true;
+                        Self::remove_library_at(self, index, 1);
+                        return;
+                    }
+
+                    update_call(data, UpdateState::ReloadFailed(err), Some(&previous));
+                    return;
+                }
+
+                let dirs = Self::watch_dirs(previous.original_path.as_ref(), &previous.links);
+                let leaked = self.replace_lib(index, lib.clone());
+
+                // A repointed link may lead to a
+                // different directory
+                Self::watch_lib(self, &lib, &dirs);
+                Self::unwatch_unused(self, dirs);
+
+                // Only hold on to the old version if it
+                // has been leaked so it can be reported
+                let leaked = if leaked { Some(previous) } else { None };
+
+                if let Some(mismatch) = mismatch {
+                    update_call(data, mismatch, Some(&lib));
+                }
+
                 update_call(data, UpdateState::After, Some(&lib));
+
+                if let Some(old) = leaked {
+                    update_call(data, UpdateState::UnloadTimedOut, Some(&old));
+                }
             }
 
             Err(err) => {
-                update_call(data, UpdateState::ReloadFailed(err), None);
-                //println!("Unable to reload lib {:?} err {:?}", file_path, err); // Removed due to move in previous line
+                update_call(data, UpdateState::ReloadFailed(err), Some(&previous));
             }
         }
     }
 
-    unsafe fn try_load_library(&self, name: &str, name_format: PlatformName) -> Result<Arc<Lib>> {
+    unsafe fn try_load_library(
+        &self,
+        name: &str,
+        name_format: PlatformName,
+        checks: &LibChecks,
+    ) -> Result<Arc<Lib>> {
         match Self::search_dirs(self, name, name_format) {
-            Some(path) => Self::load_library(self, &path),
+            Some(path) => Self::load_plugin(self, &path, LibId::next(), 0, checks),
             None => Err(Error::Find(name.into())),
         }
     }
 
-    unsafe fn load_library(&self, full_path: &PathBuf) -> Result<Arc<Lib>> {
+    // Loads a library, runs the checks on it and
+    // calls its dr_plugin_init, dropping (and so
+    // unloading) it again if any of that fails
+    unsafe fn load_plugin(
+        &self,
+        path: &Path,
+        id: LibId,
+        generation: u64,
+        checks: &LibChecks,
+    ) -> Result<Arc<Lib>> {
+        let lib = Self::load_library(self, path, id, generation, checks)?;
+        checks.after_load(&lib)?;
+
+        lifecycle::init(&lib, self.host_context)?;
+        Ok(lib)
+    }
+
+    unsafe fn load_library(
+        &self,
+        full_path: &Path,
+        id: LibId,
+        generation: u64,
+        checks: &LibChecks,
+    ) -> Result<Arc<Lib>> {
         let path;
-        let original_path;
+        let content_hash;
+        let original_path = Some(Self::canonical_path(full_path));
+        let links = Self::link_chain(full_path);
 
         if let Some(sd) = self.shadow_dir.as_ref() {
-            path = Self::format_filename(sd.path(), full_path);
-            Self::try_copy(full_path, &path)?;
-            original_path = Some(full_path.clone());
+            path = Self::format_filename(sd.path(), full_path, self.timestamps, id, generation);
+            content_hash = Self::try_copy(full_path, &path)?;
         } else {
-            original_path = None;
-            path = full_path.clone();
+            path = full_path.to_path_buf();
+            content_hash =
+                Self::hash_file(&path).map_err(|e| Error::Read(e, path.clone()))?;
         }
 
-        Self::init_library(original_path, path)
+        // Catches truncated files and builds for
+        // other targets with a clearer error than
+        // the one from the dynamic loader. Done on
+        // the copy so the artifact changing in
+        // between can't slip past the checks, but
+        // errors name the file that was built
+        let shown = original_path.as_deref().unwrap_or(&path);
+        #[cfg(all(unix, not(target_vendor = "apple")))]
+        elf::check(&path, shown)?;
+        checks.before_load(&path, shown)?;
+
+        let in_place = self.shadow_dir.is_none();
+        let lib =
+            Self::init_library(original_path, links, path, id, generation, content_hash, in_place)?;
+
+        // Dropping the library unloads it again
+        fingerprint::check(&lib, self.api_version)?;
+        Ok(lib)
     }
 
-    unsafe fn init_library(org_path: Option<PathBuf>, path: PathBuf) -> Result<Arc<Lib>> {
-        match Library::new(&path) {
-            Ok(l) => Ok(Arc::new(Lib {
-                original_path: org_path,
-                loaded_path: path,
-                lib: l,
-            })),
-            Err(e) => Err(Error::Load(e)),
+    // Without a shadow directory the library is
+    // reloaded from where it was built. That only
+    // works on Linux and only if the new version
+    // was written to a new file (which is what
+    // linkers do) as the old file is still mapped
+    fn check_in_place(&self, previous: &Lib, path: &Path) -> Result<()> {
+        if self.shadow_dir.is_some() {
+            return Ok(());
+        }
+
+        if !cfg!(target_os = "linux") {
+            return Err(Error::ShadowDirRequired(path.to_path_buf()));
+        }
+
+        match Self::file_id(path) {
+            Some(id) if Some(id) == previous.file_id => {
+                Err(Error::ShadowDirRequired(path.to_path_buf()))
+            }
+            _ => Ok(()),
         }
     }
 
-    fn should_reload(reload_path: &Path, lib: &Lib) -> bool {
-        if let Some(p) = lib.original_path.as_ref() {
-            // Check if file names match.
-            if reload_path.file_name() == p.file_name() {
-                return true;
+    unsafe fn init_library(
+        org_path: Option<PathBuf>,
+        links: Vec<PathBuf>,
+        path: PathBuf,
+        id: LibId,
+        generation: u64,
+        content_hash: u64,
+        in_place: bool,
+    ) -> Result<Arc<Lib>> {
+        let (lib, file) = if in_place {
+            Self::open_in_place(&path)?
+        } else {
+            (Library::new(&path).map_err(Error::Load)?, None)
+        };
+
+        let file_id = match file.as_ref() {
+            Some(f) => f.metadata().ok().and_then(|m| Self::metadata_id(&m)),
+            None => Self::file_id(&path),
+        };
+
+        Ok(Arc::new(Lib {
+            original_path: org_path,
+            link_path: links.first().cloned(),
+            loaded_path: path,
+            lib,
+            id,
+            generation,
+            content_hash,
+            file_id,
+            links,
+            _file: file,
+            calls: Arc::default(),
+        }))
+    }
+
+    // The dynamic loader hands back the library
+    // that is already loaded when it's opened with
+    // the same name again, even if the file has
+    // been replaced. Opening it through the file
+    // descriptor gives each version its own name
+    // for as long as the file is kept open.
+    //
+    // An old version that couldn't be unmapped
+    // (```-z nodelete```, another ```dlopen```
+    // of it, etc) keeps the name of its closed
+    // descriptor, so the descriptor is duplicated
+    // until it has a name that isn't loaded yet.
+    #[cfg(target_os = "linux")]
+    unsafe fn open_in_place(path: &Path) -> Result<(Library, Option<fs::File>)> {
+        use libloading::os::unix::{Library as UnixLibrary, RTLD_NOW};
+        use std::os::unix::io::AsRawFd;
+
+        // Same value for glibc and musl
+        const RTLD_NOLOAD: std::os::raw::c_int = 4;
+
+        let read_err = |e| Error::Read(e, path.to_path_buf());
+        let mut file = fs::File::open(path).map_err(read_err)?;
+        // Kept open while looking so the
+        // duplicates get new numbers
+        let mut taken = Vec::new();
+
+        let fd_path = loop {
+            let fd_path = format!("/proc/self/fd/{}", file.as_raw_fd());
+
+            match UnixLibrary::open(Some(&fd_path), RTLD_NOW | RTLD_NOLOAD) {
+                Ok(_loaded) => {
+                    let next = file.try_clone().map_err(read_err)?;
+                    taken.push(std::mem::replace(&mut file, next));
+                }
+                Err(_) => break fd_path,
             }
+        };
+
+        let lib = Library::new(fd_path).map_err(Error::Load)?;
+        Ok((lib, Some(file)))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    unsafe fn open_in_place(path: &Path) -> Result<(Library, Option<fs::File>)> {
+        Ok((Library::new(path).map_err(Error::Load)?, None))
+    }
+
+    fn should_reload(&self, reload_path: &Path, original: &Path) -> bool {
+        match self.path_match {
+            PathMatch::Exact => Self::same_file(reload_path, original),
+            PathMatch::FileName => reload_path.file_name() == original.file_name(),
+        }
+    }
+
+    // True if ```path``` is the library at
+    // ```original``` or one of the links leading to
+    // it. Links are compared without following
+    // them as they may point somewhere else now
+    fn matches_lib(&self, path: &Path, original: &Path, links: &[PathBuf]) -> bool {
+        if Self::should_reload(self, path, original) {
+            return true;
+        }
+
+        links.iter().any(|link| match self.path_match {
+            PathMatch::Exact => Self::link_location(path) == *link,
+            PathMatch::FileName => path.file_name() == link.file_name(),
+        })
+    }
+
+    fn is_tracked(&self, path: &Path) -> bool {
+        let tracked = self.libs.iter().any(|l| match l.lib.original_path.as_ref() {
+            Some(original) => Self::matches_lib(self, path, original, &l.lib.links),
+            None => false,
+        });
+
+        tracked
+            || self.waiting.iter().any(|l| {
+                Self::matches_lib(self, path, &l.original_path, &l.links)
+            })
+    }
+
+    // True if ```path``` is a shared library in
+    // ```dir``` that matches its pattern
+    fn in_dir(dir: &PluginDir, path: &Path) -> bool {
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return false,
+        };
+
+        let lib_name = Self::get_dynamiclib_name("lib");
+
+        Self::link_location(path).parent() == Some(dir.path.as_path())
+            && path.extension() == Path::new(&lib_name).extension()
+            && dir.pattern.matches(name)
+    }
+
+    // The matching libraries in ```dir```, sorted
+    // so they are loaded in a stable order
+    fn dir_libs(dir: &PluginDir) -> Result<Vec<PathBuf>> {
+        let entries = fs::read_dir(&dir.path).map_err(|e| Error::Read(e, dir.path.clone()))?;
+
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && Self::in_dir(dir, path))
+            .collect();
+
+        files.sort();
+        Ok(files)
+    }
+
+    fn same_file(a: &Path, b: &Path) -> bool {
+        if Self::canonical_path(a) == Self::canonical_path(b) {
+            return true;
+        }
+
+        // Catches hard links and bind mounts that
+        // point at the same file through different
+        // paths
+        match (Self::file_id(a), Self::file_id(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn file_id(path: &Path) -> Option<(u64, u64)> {
+        Self::metadata_id(&fs::metadata(path).ok()?)
+    }
+
+    #[cfg(unix)]
+    fn metadata_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.dev(), metadata.ino()))
+    }
+
+    #[cfg(not(unix))]
+    fn metadata_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+        None
+    }
+
+    // The file may be missing (removed or in the
+    // middle of being written) so fall back to
+    // canonicalizing the parent directory
+    fn canonical_path(path: &Path) -> PathBuf {
+        path.canonicalize()
+            .unwrap_or_else(|_| Self::link_location(path))
+    }
+
+    // Canonicalizes the parent directory only so a
+    // symlink isn't followed
+    fn link_location(path: &Path) -> PathBuf {
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => parent
+                .canonicalize()
+                .map(|p| p.join(name))
+                .unwrap_or_else(|_| path.to_path_buf()),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    // The symlinks followed to get from ```path```
+    // to the library, starting with ```path```
+    // itself. Empty if ```path``` isn't a link
+    fn link_chain(path: &Path) -> Vec<PathBuf> {
+        let mut links = Vec::new();
+        let mut current = Self::link_location(path);
+
+        // Same limit as Linux so a cycle ends
+        while links.len() < 40 {
+            let target = match fs::read_link(&current) {
+                Ok(target) => target,
+                Err(_) => break,
+            };
+
+            // Relative targets start from the
+            // directory of the link
+            let next = match current.parent() {
+                Some(parent) => parent.join(target),
+                None => target,
+            };
+
+            links.push(current);
+            current = Self::link_location(&next);
         }
 
-        false
+        links
     }
 
     fn search_dirs(&self, name: &str, name_format: PlatformName) -> Option<PathBuf> {
         let lib_name = Self::get_library_name(name, name_format);
 
+        if let Search::Custom(resolver) = &self.search {
+            return resolver
+                .resolve(&lib_name)
+                .and_then(|path| Self::is_file(&path));
+        }
+
         // 1. Search the current directory
         if let Some(path) = Self::search_current_dir(&lib_name) {
             return Some(path);
@@ -487,8 +1803,11 @@ impl<'a> DynamicReload {
             return Some(path);
         }
 
-        // 3. Search the executable dir and then go backwards
-        Self::search_backwards_from_exe(&lib_name)
+        // 3. Search the executable dir and then go backwards if allowed
+        match self.search {
+            Search::Backwards => Self::search_backwards_from_exe(&lib_name),
+            _ => Self::search_exe_dir(&lib_name),
+        }
     }
 
     fn search_current_dir(name: &String) -> Option<PathBuf> {
@@ -523,21 +1842,24 @@ impl<'a> DynamicReload {
         }
     }
 
+    fn search_exe_dir(lib_name: &String) -> Option<PathBuf> {
+        let exe_path = env::current_exe().unwrap_or_default();
+        let dir = Self::get_parent_dir(&exe_path)?;
+        Self::is_file(&dir.join(lib_name))
+    }
+
     fn search_backwards_from_exe(lib_name: &String) -> Option<PathBuf> {
         let exe_path = env::current_exe().unwrap_or_default();
         Self::search_backwards_from_file(&exe_path, lib_name)
     }
 
-    fn get_temp_dir(shadow_dir: Option<&str>) -> Option<TempDir> {
+    fn get_temp_dir(shadow_dir: Option<&Path>) -> Result<Option<TempDir>> {
         match shadow_dir {
             Some(dir) => match TempDir::new_in(dir, "shadow_libs") {
-                Ok(td) => Some(td),
-                Err(er) => {
-                    println!("Unable to create tempdir {}", er);
-                    None
-                }
+                Ok(td) => Ok(Some(td)),
+                Err(e) => Err(Error::ShadowDir(e, dir.to_path_buf())),
             },
-            _ => None,
+            _ => Ok(None),
         }
     }
 
@@ -565,7 +1887,9 @@ impl<'a> DynamicReload {
     // ms before we try again, if we can't do it
     // within 1 sec we give up
     //
-    fn try_copy(src: &Path, dest: &Path) -> Result<()> {
+    // Returns the content hash of the copy.
+    //
+    fn try_copy(src: &Path, dest: &Path) -> Result<u64> {
         for _ in 0..10 {
             if let Ok(file) = fs::metadata(src) {
                 let len = file.len();
@@ -574,8 +1898,10 @@ impl<'a> DynamicReload {
                     // ignore copy errors, library
                     // file might be locked by the
                     // compiler
-                    if fs::copy(&src, &dest).is_ok() {
-                        return Ok(());
+                    if fs::copy(src, dest).is_ok() {
+                        if let Ok(hash) = Self::hash_file(dest) {
+                            return Ok(hash);
+                        }
                     }
                 }
             }
@@ -586,36 +1912,41 @@ impl<'a> DynamicReload {
         Err(Error::CopyTimeOut(src.to_path_buf(), dest.to_path_buf()))
     }
 
-    fn get_watcher(
-        tx: Sender<notify::DebouncedEvent>,
-        debounce_duration: Duration,
-    ) -> Option<RecommendedWatcher> {
-        match notify::watcher(tx, debounce_duration) {
-            Ok(watcher) => Some(watcher),
-            Err(e) => {
-                println!(
-                    "Unable to create file watcher, no dynamic reloading will be done, \
-                     error: {:?}",
-                    e
-                );
-                None
+    fn hash_file(path: &Path) -> io::Result<u64> {
+        let mut file = fs::File::open(path)?;
+        let mut hasher = DefaultHasher::new();
+        let mut buffer = [0u8; 64 * 1024];
+
+        loop {
+            match file.read(&mut buffer)? {
+                0 => return Ok(hasher.finish()),
+                len => hasher.write(&buffer[..len]),
             }
         }
     }
 
-    fn get_search_paths(search_paths: Option<Vec<&str>>) -> Vec<PathBuf> {
-        match search_paths {
-            Some(paths) => paths
-                .iter()
-                .map(|p| {
-                    let path_buf = Path::new(p).to_path_buf();
-                    path_buf.canonicalize().unwrap_or(path_buf)
-                })
-                .collect(),
-            None => Vec::new(),
+    fn get_watcher(
+        tx: Sender<WatchEvent>,
+        debounce_duration: Duration,
+        mode: WatchMode,
+        poll_interval: Duration,
+    ) -> Result<Box<dyn WatchBackend>> {
+        let raw_tx = Debouncer::spawn(debounce_duration, tx);
+
+        match mode {
+            WatchMode::Auto => Ok(Box::new(FallbackBackend::new(raw_tx, poll_interval))),
+            WatchMode::Native => Ok(Box::new(NotifyBackend::new(raw_tx)?)),
+            WatchMode::Poll => Ok(Box::new(PollBackend::new(raw_tx, poll_interval))),
         }
     }
 
+    fn canonicalize_paths<I: IntoIterator<Item = PathBuf>>(paths: I) -> Vec<PathBuf> {
+        paths
+            .into_iter()
+            .map(|path_buf| path_buf.canonicalize().unwrap_or(path_buf))
+            .collect()
+    }
+
     fn get_library_name(name: &str, name_format: PlatformName) -> String {
         if name_format == PlatformName::Yes {
             Self::get_dynamiclib_name(name)
@@ -624,26 +1955,202 @@ impl<'a> DynamicReload {
         }
     }
 
-    fn remove_lib(&mut self, idx: usize) {
-        #[cfg(feature = "no-unload")]
-        std::mem::forget(self.libs.swap_remove(idx));
+    // Returns true if the library was leaked
+    fn remove_lib(&mut self, idx: usize) -> bool {
+        let TrackedLib { lib, slot, stopped, .. } = self.libs.swap_remove(idx);
+        slot.store(None);
+
+        // Safe as whoever added the library took
+        // responsibility for running its code
+        if !stopped {
+            unsafe { lifecycle::shutdown(&lib) };
+        }
+
+        Self::release_lib(self, lib)
+    }
+
+    // Returns true if the old library was leaked
+    fn replace_lib(&mut self, idx: usize, lib: Arc<Lib>) -> bool {
+        let tracked = &mut self.libs[idx];
+        tracked.slot.store(Some(lib.clone()));
+        let old = std::mem::replace(&mut tracked.lib, lib);
+        Self::release_lib(self, old)
+    }
+
+    // Waits for the calls in flight before letting
+    // go of ```lib```. The library is leaked if
+    // they don't finish in time as unloading it
+    // would pull the code out from under them
+    fn release_lib(&self, lib: Arc<Lib>) -> bool {
+        if self.unload == UnloadPolicy::Keep {
+            std::mem::forget(lib);
+            return false;
+        }
+
+        if lib.calls.wait_idle(self.unload_timeout) {
+            return false;
+        }
+
+        std::mem::forget(lib);
+        true
+    }
+
+    // ```borrowed``` is the number of references
+    // held by the caller that shouldn't be
+    // reported as outside references
+    fn remove_library_at(&mut self, index: usize, borrowed: usize) -> RemoveState {
+        let lib = self.libs[index].lib.clone();
+        let leaked = self.remove_lib(index);
+
+        Self::unwatch_unused(self, Self::watch_dirs(lib.original_path.as_ref(), &lib.links));
+
+        if let Some(path) = lib.link_path.as_ref().or(lib.original_path.as_ref()) {
+            // Copies that are still loaded may be
+            // locked by the OS so ignore errors
+            for copy in self.shadow_copies(path, lib.id) {
+                let _ = fs::remove_file(copy);
+            }
+        }
+
+        if self.unload == UnloadPolicy::Keep {
+            return RemoveState::Kept;
+        }
+
+        if leaked {
+            return RemoveState::Leaked;
+        }
+
+        // Don't count the clone made above
+        match Arc::strong_count(&lib) - 1 - borrowed {
+            0 => RemoveState::Unloaded,
+            count => RemoveState::StillReferenced(count),
+        }
+    }
+
+    // The directory of a library and the ones of
+    // the links leading to it
+    fn watch_dirs(original: Option<&PathBuf>, links: &[PathBuf]) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = original
+            .into_iter()
+            .chain(links)
+            .filter_map(|path| Self::parent_dir(path))
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        dirs
+    }
+
+    // Watches the directories of ```lib``` that
+    // aren't in ```watched``` yet
+    fn watch_lib(&mut self, lib: &Lib, watched: &[PathBuf]) {
+        if let Some(w) = self.watcher.as_mut() {
+            for dir in Self::watch_dirs(lib.original_path.as_ref(), &lib.links) {
+                if !watched.contains(&dir) {
+                    let _ = w.watch(&dir);
+                }
+            }
+        }
+    }
+
+    // Stops watching the directories in ```dirs```
+    // that no library needs anymore
+    fn unwatch_unused(&mut self, dirs: Vec<PathBuf>) {
+        for dir in dirs {
+            let still_watched = self.libs.iter().any(|l| {
+                Self::watch_dirs(l.lib.original_path.as_ref(), &l.lib.links).contains(&dir)
+            }) || self.waiting.iter().any(|l| {
+                Self::watch_dirs(Some(&l.original_path), &l.links).contains(&dir)
+            }) || self.dirs.iter().any(|d| d.path == dir);
+
+            if !still_watched {
+                if let Some(w) = self.watcher.as_mut() {
+                    let _ = w.unwatch(&dir);
+                }
+            }
+        }
+    }
+
+    fn parent_dir(path: &Path) -> Option<PathBuf> {
+        let parent = path.parent()?;
 
-        #[cfg(not(feature = "no-unload"))]
-        self.libs.swap_remove(idx);
+        if cfg!(windows) {
+            parent.canonicalize().ok()
+        } else {
+            Some(parent.to_path_buf())
+        }
     }
 
-    #[cfg(not(feature = "no-timestamps"))]
-    fn format_filename(shadow_dir: &Path, full_path: &Path) -> PathBuf {
+    // All the copies of the library ```id``` named
+    // after ```original``` in the shadow
+    // directory, including the ones from previous
+    // reloads.
+    fn shadow_copies(&self, original: &Path, id: LibId) -> Vec<PathBuf> {
+        let (shadow_dir, file_name) = match (self.shadow_dir.as_ref(), original.file_name()) {
+            (Some(dir), Some(name)) => (dir.path(), name.to_string_lossy()),
+            _ => return Vec::new(),
+        };
+
+        let entries = match fs::read_dir(shadow_dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = match path.file_name() {
+                    Some(name) => name.to_string_lossy(),
+                    None => return false,
+                };
+
+                // See format_filename
+                let prefix = match name.strip_suffix(file_name.as_ref()) {
+                    Some(prefix) => prefix.strip_suffix('_'),
+                    None => None,
+                };
+
+                let parts: Vec<&str> = match prefix {
+                    Some(prefix) => prefix.split('-').collect(),
+                    None => return false,
+                };
+
+                let numbers = parts
+                    .iter()
+                    .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+
+                numbers
+                    && (parts.len() == 2 || parts.len() == 3)
+                    && parts[parts.len() - 2] == id.0.to_string()
+            })
+            .collect()
+    }
+
+    // Copies are named "<id>-<generation>_<file>"
+    // (with "<millis>-" in front when timestamps
+    // are on). The new version is copied while the
+    // old one is still loaded so the name has to
+    // differ from every other copy, otherwise the
+    // loaded file is overwritten and the dynamic
+    // loader hands back the old library
+    fn format_filename(
+        shadow_dir: &Path,
+        full_path: &Path,
+        timestamps: bool,
+        id: LibId,
+        generation: u64,
+    ) -> PathBuf {
+        let filename = full_path.file_name().unwrap().to_str().unwrap();
+        let prefix = format!("{}-{}", id.0, generation);
+
+        if !timestamps {
+            return shadow_dir.join(format!("{}_{}", prefix, filename));
+        }
+
         let ts = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .expect("Time went backwards");
-        let filename = full_path.file_name().unwrap();
-        shadow_dir.join(format!("{}_{}", ts.as_millis(), filename.to_str().unwrap()))
-    }
-
-    #[cfg(feature = "no-timestamps")]
-    fn format_filename(shadow_dir: &Path, full_path: &PathBuf) -> PathBuf {
-        shadow_dir.join(full_path.file_name().unwrap())
+        shadow_dir.join(format!("{}-{}_{}", ts.as_millis(), prefix, filename))
     }
 
     /// Formats dll name on Windows ("test_foo" -> "test_foo.dll")
@@ -671,14 +2178,15 @@ impl<'a> DynamicReload {
     }
 }
 
+/// Libraries are equal if they have the same
+/// [LibId](struct.LibId.html), that is if they
+/// are different versions of the same library.
+/// Compare ```generation``` as well to check for
+/// the same version.
 impl PartialEq for Lib {
     fn eq(&self, other: &Lib) -> bool {
-        self.original_path == other.original_path
+        self.id == other.id
     }
-
-    /*
-    fn ne(&self, other: &Lib) -> bool {
-        self.original_path != other.original_path
-    }
-    */
 }
+
+impl Eq for Lib {}
@@ -0,0 +1,48 @@
+use crate::{Error, Lib, Result};
+use std::{ffi::c_void, ptr};
+
+const INIT_SYMBOL: &[u8] = b"dr_plugin_init\0";
+const SHUTDOWN_SYMBOL: &[u8] = b"dr_plugin_shutdown\0";
+
+type InitFn = unsafe extern "C" fn(*mut c_void) -> i32;
+type ShutdownFn = unsafe extern "C" fn();
+
+/// Pointer passed to ```dr_plugin_init```. Set
+/// with
+/// [host_context](struct.DynamicReloadBuilder.html#method.host_context)
+/// which makes the caller responsible for it
+/// being usable from the thread doing the
+/// reloads.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HostContext(pub(crate) *mut c_void);
+
+unsafe impl Send for HostContext {}
+
+impl Default for HostContext {
+    fn default() -> HostContext {
+        HostContext(ptr::null_mut())
+    }
+}
+
+/// Calls ```dr_plugin_init``` if exported by
+/// ```lib```. A non-zero return value is turned
+/// into ```Error::PluginInit```.
+pub(crate) unsafe fn init(lib: &Lib, host: HostContext) -> Result<()> {
+    let init = match lib.lib.get::<InitFn>(INIT_SYMBOL) {
+        Ok(init) => init,
+        Err(_) => return Ok(()),
+    };
+
+    match init(host.0) {
+        0 => Ok(()),
+        code => Err(Error::PluginInit(code, lib.loaded_path.clone())),
+    }
+}
+
+/// Calls ```dr_plugin_shutdown``` if exported by
+/// ```lib```
+pub(crate) unsafe fn shutdown(lib: &Lib) {
+    if let Ok(shutdown) = lib.lib.get::<ShutdownFn>(SHUTDOWN_SYMBOL) {
+        shutdown();
+    }
+}
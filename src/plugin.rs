@@ -0,0 +1,130 @@
+use crate::symbol::LibSlot;
+use crate::{Error, Lib, PluginDecl, PluginVTable, Result};
+use std::{fmt, marker::PhantomData, sync::Arc};
+
+const VTABLE_SYMBOL: &[u8] = b"dr_plugin_vtable\0";
+const VTABLE_NAME: &str = "dr_plugin_vtable";
+
+/// Checks that ```lib``` exports a vtable with
+/// the ```expected``` layout hash
+pub(crate) unsafe fn check_layout(lib: &Lib, expected: u64) -> Result<()> {
+    let hash = lib
+        .lib
+        .get::<*const u64>(VTABLE_SYMBOL)
+        .map_err(|e| Error::Symbol(e, VTABLE_NAME.to_string()))?;
+
+    match **hash {
+        found if found == expected => Ok(()),
+        found => Err(Error::PluginLayout(
+            expected,
+            found,
+            lib.loaded_path.clone(),
+        )),
+    }
+}
+
+/// Typed vtable of a plugin that stays valid
+/// across reloads.
+///
+/// Returned by
+/// [add_plugin](struct.DynamicReload.html#method.add_plugin).
+/// Like
+/// [ReloadableSymbol](struct.ReloadableSymbol.html)
+/// the vtable is looked up again the first time
+/// [get](struct.Plugin.html#method.get) is called
+/// after a reload and the version it came from is
+/// kept loaded until then.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut math = dr.add_plugin::<MathApi>("math", PlatformName::Yes)?;
+///
+/// loop {
+///     dr.update(&Plugins::reload_callback, &mut plugs);
+///     println!("Value {}", (math.get()?.add)(1, 2));
+/// }
+/// ```
+pub struct Plugin<V> {
+    slot: LibSlot,
+    bound: Option<(Arc<Lib>, *const V)>,
+    _vtable: PhantomData<V>,
+}
+
+// The vtable points into the library that is
+// kept alive by the Arc next to it
+unsafe impl<V: Sync> Send for Plugin<V> {}
+
+impl<V: PluginVTable> Plugin<V> {
+    pub(crate) unsafe fn new(slot: LibSlot) -> Result<Plugin<V>> {
+        let mut plugin = Plugin {
+            slot,
+            bound: None,
+            _vtable: PhantomData,
+        };
+
+        plugin.bind()?;
+        Ok(plugin)
+    }
+
+    /// Returns the vtable of the current version
+    /// of the plugin, looking it up again if the
+    /// plugin has been reloaded since the last
+    /// call.
+    ///
+    /// Fails with ```Error::Removed``` if the
+    /// plugin is no longer loaded.
+    pub fn get(&mut self) -> Result<&V> {
+        // Safe as the layout of every version has
+        // been checked when it was loaded
+        unsafe { self.bind()? };
+
+        match self.bound.as_ref() {
+            Some((_, vtable)) => Ok(unsafe { &**vtable }),
+            None => Err(Error::Removed(VTABLE_NAME.to_string())),
+        }
+    }
+
+    /// The version of the library the vtable was
+    /// last looked up in.
+    pub fn lib(&self) -> Option<&Arc<Lib>> {
+        self.bound.as_ref().map(|(lib, _)| lib)
+    }
+
+    unsafe fn bind(&mut self) -> Result<()> {
+        let current = match self.slot.load_full() {
+            Some(lib) => lib,
+            None => {
+                self.bound = None;
+                return Err(Error::Removed(VTABLE_NAME.to_string()));
+            }
+        };
+
+        if let Some((lib, _)) = self.bound.as_ref() {
+            if Arc::ptr_eq(lib, &current) {
+                return Ok(());
+            }
+        }
+
+        self.bound = None;
+
+        check_layout(&current, V::LAYOUT_HASH)?;
+
+        let decl = current
+            .lib
+            .get::<*const PluginDecl<V>>(VTABLE_SYMBOL)
+            .map_err(|e| Error::Symbol(e, VTABLE_NAME.to_string()))?;
+        let vtable = std::ptr::addr_of!((**decl).vtable);
+
+        self.bound = Some((current, vtable));
+        Ok(())
+    }
+}
+
+impl<V> fmt::Debug for Plugin<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Plugin")
+            .field("lib", &self.bound.as_ref().map(|(lib, _)| &lib.loaded_path))
+            .finish()
+    }
+}
@@ -0,0 +1,145 @@
+// No other parts of the crate are used here so
+// test_plugin.rs can include the file
+
+/// A ```#[repr(C)]``` struct of functions that a
+/// plugin exports with
+/// [declare_plugin!](macro.declare_plugin.html).
+///
+/// Implemented by
+/// [plugin_vtable!](macro.plugin_vtable.html)
+/// which derives ```LAYOUT_HASH``` from how the
+/// struct is spelled, see there for what this
+/// does and doesn't catch.
+///
+/// # Safety
+/// ```LAYOUT_HASH``` has to change whenever the
+/// layout or the signatures of the struct change
+/// as it's the only thing checked before the
+/// functions are called.
+pub unsafe trait PluginVTable: Sized + 'static {
+    /// Hash of the layout of the struct.
+    const LAYOUT_HASH: u64;
+}
+
+/// What
+/// [declare_plugin!](macro.declare_plugin.html)
+/// exports as ```dr_plugin_vtable```. The hash
+/// comes first so it can be checked before the
+/// vtable is read.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PluginDecl<V> {
+    /// ```LAYOUT_HASH``` of the vtable the plugin
+    /// was built with.
+    pub layout_hash: u64,
+    /// The functions of the plugin.
+    pub vtable: V,
+}
+
+impl<V: PluginVTable> PluginDecl<V> {
+    /// Pairs ```vtable``` with its layout hash.
+    pub const fn new(vtable: V) -> PluginDecl<V> {
+        PluginDecl {
+            layout_hash: V::LAYOUT_HASH,
+            vtable,
+        }
+    }
+}
+
+/// FNV-1a hash of the spelling of a vtable
+/// together with its size and alignment. Used by
+/// [plugin_vtable!](macro.plugin_vtable.html).
+#[doc(hidden)]
+pub const fn layout_hash(desc: &str, size: usize, align: usize) -> u64 {
+    const PRIME: u64 = 0x100_0000_01b3;
+
+    let bytes = desc.as_bytes();
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(PRIME);
+        i += 1;
+    }
+
+    hash ^= size as u64;
+    hash = hash.wrapping_mul(PRIME);
+    hash ^= align as u64;
+    hash.wrapping_mul(PRIME)
+}
+
+/// Defines a ```#[repr(C)]``` vtable struct and
+/// implements [PluginVTable](trait.PluginVTable.html)
+/// for it. The struct is meant to live in a crate
+/// shared by the host and the plugins.
+///
+/// ```LAYOUT_HASH``` is a hash of the signature
+/// spelling: the name of the struct and the
+/// names and types of the fields as they are
+/// written, plus the size and alignment of the
+/// struct. The types themselves aren't looked
+/// at. Adding, removing, reordering or retyping
+/// a field changes the hash, but changing what a
+/// type name refers to (a type alias, a struct
+/// passed by pointer, etc) doesn't, and the same
+/// type spelled differently (```u32``` and
+/// ```std::primitive::u32```) gives a different
+/// hash. Rename the struct (```MathApiV2```)
+/// when such a change has to be caught.
+///
+/// # Examples
+///
+/// ```ignore
+/// dynamic_reload::plugin_vtable! {
+///     pub struct MathApi {
+///         pub add: extern "C" fn(i32, i32) -> i32,
+///         pub name: extern "C" fn() -> *const c_char,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! plugin_vtable {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident : $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(C)]
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty),*
+        }
+
+        unsafe impl $crate::PluginVTable for $name {
+            const LAYOUT_HASH: u64 = $crate::layout_hash(
+                concat!(stringify!($name), $("|", stringify!($field), ":", stringify!($ty)),*),
+                ::std::mem::size_of::<$name>(),
+                ::std::mem::align_of::<$name>(),
+            );
+        }
+    };
+}
+
+/// Exports the vtable of a plugin so it can be
+/// loaded with
+/// [add_plugin](struct.DynamicReload.html#method.add_plugin).
+///
+/// # Examples
+///
+/// ```ignore
+/// extern "C" fn add(a: i32, b: i32) -> i32 {
+///     a + b
+/// }
+///
+/// dynamic_reload::declare_plugin!(MathApi, MathApi { add, name });
+/// ```
+#[macro_export]
+macro_rules! declare_plugin {
+    ($vtable:ty, $value:expr) => {
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        pub static dr_plugin_vtable: $crate::PluginDecl<$vtable> = $crate::PluginDecl::new($value);
+    };
+}
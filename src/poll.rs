@@ -0,0 +1,131 @@
+use crate::debouncer::RawEvent;
+use crate::watcher::WatchBackend;
+use crate::{DynamicReload, Error, Result};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{mpsc::Sender, Arc, Mutex, Weak},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+// What is compared between two scans of a file.
+// For symlinks it's the link itself together with
+// its target, the file it points to is in a
+// directory that is watched on its own
+type FileState = (u64, Option<SystemTime>, Option<(u64, u64)>, Option<PathBuf>);
+
+type Snapshots = BTreeMap<PathBuf, BTreeMap<PathBuf, FileState>>;
+
+/// Backend that scans the watched directories at
+/// a fixed interval and compares the size,
+/// modification time and inode of the files
+/// and the targets of symlinks.
+/// Used where the native watcher isn't available
+/// (network file systems, bind mounts, running
+/// out of inotify watches, etc).
+pub(crate) struct PollBackend {
+    dirs: Arc<Mutex<Snapshots>>,
+}
+
+impl PollBackend {
+    pub(crate) fn new(tx: Sender<RawEvent>, interval: Duration) -> PollBackend {
+        let dirs = Arc::new(Mutex::new(BTreeMap::new()));
+        let weak = Arc::downgrade(&dirs);
+
+        // Stops once the backend has been dropped
+        thread::spawn(move || Self::run(weak, interval, tx));
+
+        PollBackend { dirs }
+    }
+
+    fn run(dirs: Weak<Mutex<Snapshots>>, interval: Duration, tx: Sender<RawEvent>) {
+        loop {
+            thread::sleep(interval);
+
+            let dirs = match dirs.upgrade() {
+                Some(dirs) => dirs,
+                None => return,
+            };
+
+            let mut events = Vec::new();
+
+            for (dir, files) in dirs.lock().unwrap().iter_mut() {
+                let current = Self::scan(dir);
+
+                for (path, state) in current.iter() {
+                    if files.get(path) != Some(state) {
+                        events.push(RawEvent::Modified(path.clone()));
+                    }
+                }
+
+                for path in files.keys() {
+                    if !current.contains_key(path) {
+                        events.push(RawEvent::Removed(path.clone()));
+                    }
+                }
+
+                *files = current;
+            }
+
+            for evt in events {
+                if tx.send(evt).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    // A directory that can't be read is handled as
+    // empty, so its files are reported as removed
+    fn scan(dir: &Path) -> BTreeMap<PathBuf, FileState> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return BTreeMap::new(),
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                // Doesn't follow symlinks
+                let metadata = entry.metadata().ok()?;
+
+                let target = match metadata.file_type().is_symlink() {
+                    true => Some(fs::read_link(entry.path()).ok()?),
+                    false if metadata.is_file() => None,
+                    false => return None,
+                };
+
+                let state = (
+                    metadata.len(),
+                    metadata.modified().ok(),
+                    DynamicReload::metadata_id(&metadata),
+                    target,
+                );
+
+                Some((entry.path(), state))
+            })
+            .collect()
+    }
+}
+
+impl WatchBackend for PollBackend {
+    fn watch(&mut self, dir: &Path) -> Result<()> {
+        // Fail like the native watcher does for
+        // directories that don't exist
+        fs::read_dir(dir).map_err(|e| Error::Read(e, dir.to_path_buf()))?;
+
+        self.dirs
+            .lock()
+            .unwrap()
+            .insert(dir.to_path_buf(), Self::scan(dir));
+
+        Ok(())
+    }
+
+    fn unwatch(&mut self, dir: &Path) -> Result<()> {
+        self.dirs.lock().unwrap().remove(dir);
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{Error, Lib, Result};
+use std::path::Path;
+
+/// An export a library has to have before it's
+/// loaded, see
+/// [add_library_with_symbols](struct.DynamicReload.html#method.add_library_with_symbols).
+///
+/// A signature tag additionally requires the
+/// export ```dr_sig__<name>__<tag>``` which the
+/// library declares with
+/// [declare_signature!](macro.declare_signature.html)
+/// next to the function. Changing the tag when the
+/// signature changes turns a crash into
+/// ```Error::MissingSymbols```.
+///
+/// Converted from ```"name"``` or
+/// ```"name:tag"```.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequiredSymbol {
+    name: String,
+    signature: Option<String>,
+}
+
+impl RequiredSymbol {
+    /// Requires the export ```name```
+    pub fn new(name: &str) -> RequiredSymbol {
+        RequiredSymbol {
+            name: name.to_string(),
+            signature: None,
+        }
+    }
+
+    /// Also requires the signature ```tag``` for
+    /// the export
+    pub fn signature(mut self, tag: &str) -> RequiredSymbol {
+        self.signature = Some(tag.to_string());
+        self
+    }
+
+    /// Name of the export
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn signature_symbol(&self) -> Option<String> {
+        self.signature
+            .as_ref()
+            .map(|tag| format!("dr_sig__{}__{}", self.name, tag))
+    }
+
+    /// Describes what of ```self``` isn't
+    /// exported, if anything
+    fn missing<F: Fn(&str) -> bool>(&self, exported: F) -> Option<String> {
+        if !exported(&self.name) {
+            return Some(self.name.clone());
+        }
+
+        match (self.signature.as_ref(), self.signature_symbol()) {
+            (Some(tag), Some(symbol)) if !exported(&symbol) => {
+                Some(format!("{} (signature {})", self.name, tag))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for RequiredSymbol {
+    fn from(spec: &'a str) -> RequiredSymbol {
+        match spec.split_once(':') {
+            Some((name, tag)) => RequiredSymbol::new(name).signature(tag),
+            None => RequiredSymbol::new(spec),
+        }
+    }
+}
+
+/// Exports the signature tag of a function for
+/// a [RequiredSymbol](struct.RequiredSymbol.html)
+/// with a signature.
+///
+/// # Examples
+///
+/// ```ignore
+/// #[no_mangle]
+/// pub extern "C" fn step(dt: f32) {}
+///
+/// dynamic_reload::declare_signature!(step, v2);
+/// ```
+#[macro_export]
+macro_rules! declare_signature {
+    ($name:ident, $tag:ident) => {
+        const _: () = {
+            #[export_name = concat!("dr_sig__", stringify!($name), "__", stringify!($tag))]
+            pub static SIGNATURE: u8 = 0;
+        };
+    };
+}
+
+/// Checks the dynamic symbol table of the file
+/// at ```path``` before it's loaded, errors name
+/// ```shown```
+#[cfg(all(unix, not(target_vendor = "apple")))]
+pub(crate) fn check_file(path: &Path, shown: &Path, required: &[RequiredSymbol]) -> Result<()> {
+    if required.is_empty() {
+        return Ok(());
+    }
+
+    let exports = crate::elf::exports(path, shown)?;
+    check(required, |name| exports.contains(name))
+}
+
+/// Without an ELF file to read the check is done
+/// by ```check_loaded``` instead
+#[cfg(not(all(unix, not(target_vendor = "apple"))))]
+pub(crate) fn check_file(_path: &Path, _shown: &Path, _required: &[RequiredSymbol]) -> Result<()> {
+    Ok(())
+}
+
+/// Looks the symbols up in the loaded library on
+/// platforms where the file isn't read first
+pub(crate) unsafe fn check_loaded(lib: &Lib, required: &[RequiredSymbol]) -> Result<()> {
+    if cfg!(all(unix, not(target_vendor = "apple"))) {
+        return Ok(());
+    }
+
+    check(required, |name| {
+        let symbol = format!("{}\0", name);
+        lib.lib.get::<*const u8>(symbol.as_bytes()).is_ok()
+    })
+}
+
+fn check<F: Fn(&str) -> bool>(required: &[RequiredSymbol], exported: F) -> Result<()> {
+    let missing: Vec<String> = required
+        .iter()
+        .filter_map(|symbol| symbol.missing(&exported))
+        .collect();
+
+    match missing.is_empty() {
+        true => Ok(()),
+        false => Err(Error::MissingSymbols(missing)),
+    }
+}
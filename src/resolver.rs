@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+
+/// Decides where a shared library lives on disk.
+///
+/// Used together with
+/// [Search::Custom](enum.Search.html#variant.Custom)
+/// to replace the built-in search when
+/// [add_library](struct.DynamicReload.html#method.add_library)
+/// is called. This allows a build system to
+/// decide where artifacts are located instead of
+/// having DynamicReload look for them.
+///
+/// Closures with the signature
+/// ```Fn(&str) -> Option<PathBuf>``` implement
+/// this trait.
+///
+/// # Examples
+///
+/// ```ignore
+/// let dr = DynamicReload::builder()
+///     .search(Search::custom(|name: &str| {
+///         Some(Path::new("build/artifacts").join(name))
+///     }))
+///     .build()?;
+/// ```
+pub trait LibraryResolver: Send + Sync {
+    /// Returns the path of the library named
+    /// ```lib_name``` or ```None``` if it can't
+    /// be found. ```lib_name``` has already been
+    /// formatted according to the
+    /// [PlatformName](enum.PlatformName.html)
+    /// passed to
+    /// [add_library](struct.DynamicReload.html#method.add_library)
+    fn resolve(&self, lib_name: &str) -> Option<PathBuf>;
+}
+
+impl<F> LibraryResolver for F
+where
+    F: Fn(&str) -> Option<PathBuf> + Send + Sync,
+{
+    fn resolve(&self, lib_name: &str) -> Option<PathBuf> {
+        self(lib_name)
+    }
+}
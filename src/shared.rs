@@ -0,0 +1,204 @@
+use crate::debouncer::WatchEvent;
+use crate::symbol::LibSlot;
+use crate::{
+    DynamicReload, Lib, LibId, PendingReloads, PlatformName, ReloadableSymbol, Result, UpdateState,
+};
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// How often the reload thread checks if it
+// should stop while there are no file events
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Handle to the current version of a library.
+///
+/// Returned by
+/// [DynamicReload::handle](struct.DynamicReload.html#method.handle)
+/// and
+/// [SharedDynamicReload::add_library](struct.SharedDynamicReload.html#method.add_library).
+/// The handle is updated when the library is
+/// reloaded and can be cloned and shared between
+/// threads. Reading the current version doesn't
+/// take a lock.
+#[derive(Debug, Clone)]
+pub struct LibHandle {
+    id: LibId,
+    slot: LibSlot,
+}
+
+impl LibHandle {
+    pub(crate) fn new(id: LibId, slot: LibSlot) -> LibHandle {
+        LibHandle { id, slot }
+    }
+
+    /// Identity of the library.
+    pub fn id(&self) -> LibId {
+        self.id
+    }
+
+    /// Returns the current version of the library
+    /// or ```None``` if it has been removed.
+    pub fn current(&self) -> Option<Arc<Lib>> {
+        self.slot.load_full()
+    }
+
+    /// Same as
+    /// [DynamicReload::symbol](struct.DynamicReload.html#method.symbol)
+    ///
+    /// # Safety
+    /// ```T``` has to match the type of the
+    /// symbol in every version of the library.
+    pub unsafe fn symbol<T>(&self, name: &str) -> Result<ReloadableSymbol<T>> {
+        ReloadableSymbol::new(name, self.slot.clone())
+    }
+}
+
+/// Runs the reloading of a
+/// [DynamicReload](struct.DynamicReload.html)
+/// instance on a background thread.
+///
+/// Libraries are reloaded as soon as the watcher
+/// reports a change, so there is no need to call
+/// ```update```. The new versions are published
+/// through [LibHandle](struct.LibHandle.html)s
+/// which any number of threads can read from. The
+/// callback is called on the background thread
+/// with the same states as the callback passed to
+/// ```update```.
+///
+/// The thread is stopped when this is dropped.
+///
+/// # Examples
+///
+/// ```ignore
+/// let dr = DynamicReload::builder().shadow_dir("target/debug").build()?;
+/// let shared = unsafe {
+///     SharedDynamicReload::new(dr, |state, lib| println!("{:?} {:?}", state, lib))
+/// };
+/// let handle = unsafe { shared.add_library("test_shared", PlatformName::Yes)? };
+///
+/// thread::spawn(move || loop {
+///     let lib = handle.current().unwrap();
+///     ...
+/// });
+/// ```
+#[derive(Debug)]
+pub struct SharedDynamicReload {
+    inner: Arc<Mutex<DynamicReload>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SharedDynamicReload {
+    /// Moves ```reload``` to a background thread
+    /// that reloads libraries when they change.
+    ///
+    /// # Safety
+    /// Same as
+    /// [update](struct.DynamicReload.html#method.update)
+    /// as libraries are loaded and unloaded from
+    /// the background thread.
+    pub unsafe fn new<F>(mut reload: DynamicReload, callback: F) -> SharedDynamicReload
+    where
+        F: FnMut(UpdateState, Option<&Arc<Lib>>) + Send + 'static,
+    {
+        // The thread waits for events itself so the
+        // lock is only held while reloading
+        let watch_recv = mem::replace(&mut reload.watch_recv, channel().1);
+
+        let inner = Arc::new(Mutex::new(reload));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let inner = inner.clone();
+            let stop = stop.clone();
+            thread::spawn(move || Self::run(&inner, &stop, watch_recv, callback))
+        };
+
+        SharedDynamicReload {
+            inner,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Same as
+    /// [DynamicReload::add_library](struct.DynamicReload.html#method.add_library)
+    /// but returns a handle that follows the
+    /// reloads done on the background thread.
+    ///
+    /// # Safety
+    /// Same as
+    /// [DynamicReload::add_library](struct.DynamicReload.html#method.add_library)
+    pub unsafe fn add_library(&self, name: &str, name_format: PlatformName) -> Result<LibHandle> {
+        let mut reload = self.lock();
+        let lib = reload.add_library(name, name_format)?;
+        // Can't fail as the library was just added
+        Ok(reload.handle(&lib).unwrap())
+    }
+
+    /// Gives access to the
+    /// [DynamicReload](struct.DynamicReload.html)
+    /// instance, for example to remove libraries.
+    /// Reloads are blocked while the guard is
+    /// held. Calling ```update``` on it does
+    /// nothing as the events are handled by the
+    /// background thread.
+    pub fn lock(&self) -> MutexGuard<'_, DynamicReload> {
+        self.inner.lock().unwrap()
+    }
+
+    fn run<F>(
+        inner: &Mutex<DynamicReload>,
+        stop: &AtomicBool,
+        watch_recv: Receiver<WatchEvent>,
+        mut callback: F,
+    ) where
+        F: FnMut(UpdateState, Option<&Arc<Lib>>),
+    {
+        while !stop.load(Ordering::Acquire) {
+            let first = match watch_recv.recv_timeout(STOP_CHECK_INTERVAL) {
+                Ok(evt) => evt,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            let mut reload = inner.lock().unwrap();
+            let mut pending = PendingReloads::default();
+            reload.queue_event(first, &mut pending);
+
+            while let Ok(evt) = watch_recv.try_recv() {
+                reload.queue_event(evt, &mut pending);
+            }
+
+            // Safe as the caller of new took
+            // responsibility for reloading
+            unsafe { reload.reload_libs(pending, &Self::notify, &mut callback) };
+        }
+    }
+
+    fn notify<F>(callback: &mut F, state: UpdateState, lib: Option<&Arc<Lib>>)
+    where
+        F: FnMut(UpdateState, Option<&Arc<Lib>>),
+    {
+        callback(state, lib)
+    }
+}
+
+impl Drop for SharedDynamicReload {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
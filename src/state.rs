@@ -0,0 +1,107 @@
+use crate::Lib;
+use std::ptr;
+
+const SAVE_STATE_SYMBOL: &[u8] = b"dr_save_state\0";
+const LOAD_STATE_SYMBOL: &[u8] = b"dr_load_state\0";
+
+type SaveStateFn = unsafe extern "C" fn(*mut StateBuffer);
+type LoadStateFn = unsafe extern "C" fn(u32, *const u8, usize) -> u32;
+
+/// Buffer used to move state from the old
+/// version of a library to the new one during a
+/// reload.
+///
+/// This is an optional protocol. If the old
+/// library exports
+///
+/// ```ignore
+/// #[no_mangle]
+/// pub extern "C" fn dr_save_state(state: &mut StateBuffer)
+/// ```
+///
+/// it is called before the library is unloaded.
+/// The library sets ```state.version``` to the
+/// schema version of its state and writes the
+/// state as bytes with
+/// [write](struct.StateBuffer.html#method.write).
+///
+/// If the new library exports
+///
+/// ```ignore
+/// #[no_mangle]
+/// pub extern "C" fn dr_load_state(version: u32, data: *const u8, len: usize) -> u32
+/// ```
+///
+/// it is called with the saved state once it has
+/// been loaded. The function returns the schema
+/// version it understands and should only use
+/// the data if it matches ```version```. If the
+/// versions differ
+/// ```UpdateState::StateMismatch``` is reported.
+///
+/// The layout is fixed so libraries that don't
+/// depend on this crate (or aren't written in
+/// Rust) can mirror it:
+///
+/// ```ignore
+/// struct StateBuffer {
+///     uint32_t version;
+///     void* data;
+///     void (*write)(void* data, const uint8_t* bytes, size_t len);
+/// };
+/// ```
+#[repr(C)]
+pub struct StateBuffer {
+    /// Schema version of the saved state.
+    pub version: u32,
+    data: *mut Vec<u8>,
+    write: unsafe extern "C" fn(*mut Vec<u8>, *const u8, usize),
+}
+
+impl StateBuffer {
+    /// Appends ```bytes``` to the saved state.
+    pub fn write(&mut self, bytes: &[u8]) {
+        unsafe { (self.write)(self.data, bytes.as_ptr(), bytes.len()) }
+    }
+}
+
+unsafe extern "C" fn write_state(data: *mut Vec<u8>, bytes: *const u8, len: usize) {
+    if !data.is_null() && !bytes.is_null() {
+        (*data).extend_from_slice(std::slice::from_raw_parts(bytes, len));
+    }
+}
+
+/// State saved from the old version of a library.
+#[derive(Debug)]
+pub(crate) struct SavedState {
+    pub(crate) version: u32,
+    pub(crate) data: Vec<u8>,
+}
+
+/// Calls ```dr_save_state``` if exported by
+/// ```lib```
+pub(crate) unsafe fn save_state(lib: &Lib) -> Option<SavedState> {
+    let save = lib.lib.get::<SaveStateFn>(SAVE_STATE_SYMBOL).ok()?;
+    let mut data = Vec::new();
+
+    let mut buffer = StateBuffer {
+        version: 0,
+        data: ptr::addr_of_mut!(data),
+        write: write_state,
+    };
+
+    save(&mut buffer);
+
+    Some(SavedState {
+        version: buffer.version,
+        data,
+    })
+}
+
+/// Calls ```dr_load_state``` if exported by
+/// ```lib``` and returns the version the library
+/// expected.
+pub(crate) unsafe fn load_state(lib: &Lib, state: &SavedState) -> Option<u32> {
+    let load = lib.lib.get::<LoadStateFn>(LOAD_STATE_SYMBOL).ok()?;
+    Some(load(state.version, state.data.as_ptr(), state.data.len()))
+}
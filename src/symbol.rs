@@ -0,0 +1,128 @@
+use crate::{Error, Lib, Result};
+use arc_swap::ArcSwapOption;
+use std::{fmt, sync::Arc};
+
+#[cfg(unix)]
+use libloading::os::unix::Symbol as RawSymbol;
+#[cfg(windows)]
+use libloading::os::windows::Symbol as RawSymbol;
+
+/// Current version of a tracked library. Shared
+/// between DynamicReload and the symbols handed
+/// out for it. Set to ```None``` when the library
+/// is unloaded. Reading it doesn't take a lock.
+pub(crate) type LibSlot = Arc<ArcSwapOption<Lib>>;
+
+/// A symbol that stays valid across reloads.
+///
+/// Created with
+/// [symbol](struct.DynamicReload.html#method.symbol).
+/// Instead of keeping a raw
+/// [Symbol](type.Symbol.html) around (which
+/// dangles once the library is reloaded) this
+/// keeps the name of the symbol together with
+/// the library it came from and looks the symbol
+/// up again the first time
+/// [get](struct.ReloadableSymbol.html#method.get)
+/// is called after a reload.
+///
+/// The library version the symbol was bound
+/// from is kept loaded until the symbol is
+/// rebound or dropped.
+///
+/// # Examples
+///
+/// ```ignore
+/// let lib = dr.add_library("test_shared", PlatformName::Yes)?;
+/// let mut fun = dr.symbol::<extern "C" fn() -> i32>(&lib, "shared_fun")?;
+///
+/// loop {
+///     dr.update(&Plugins::reload_callback, &mut plugs);
+///     println!("Value {}", (fun.get()?)());
+/// }
+/// ```
+pub struct ReloadableSymbol<T> {
+    name: String,
+    slot: LibSlot,
+    bound: Option<(Arc<Lib>, RawSymbol<T>)>,
+}
+
+impl<T> ReloadableSymbol<T> {
+    pub(crate) unsafe fn new(name: &str, slot: LibSlot) -> Result<ReloadableSymbol<T>> {
+        let mut symbol = ReloadableSymbol {
+            name: name.trim_end_matches('\0').to_string(),
+            slot,
+            bound: None,
+        };
+
+        symbol.bind()?;
+        Ok(symbol)
+    }
+
+    /// Name of the symbol.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the symbol from the current
+    /// version of the library, looking it up
+    /// again if the library has been reloaded
+    /// since the last call.
+    ///
+    /// Fails with ```Error::Symbol``` if the new
+    /// version of the library doesn't export the
+    /// symbol and with ```Error::Removed``` if
+    /// the library is no longer loaded.
+    pub fn get(&mut self) -> Result<&T> {
+        // Safe as the type was given when the
+        // symbol was created
+        unsafe { self.bind()? };
+
+        match self.bound.as_ref() {
+            Some((_, symbol)) => Ok(symbol),
+            None => Err(Error::Removed(self.name.clone())),
+        }
+    }
+
+    unsafe fn bind(&mut self) -> Result<()> {
+        let current = self.slot.load_full();
+
+        let current = match current {
+            Some(lib) => lib,
+            None => {
+                self.bound = None;
+                return Err(Error::Removed(self.name.clone()));
+            }
+        };
+
+        if let Some((lib, _)) = self.bound.as_ref() {
+            if Arc::ptr_eq(lib, &current) {
+                return Ok(());
+            }
+        }
+
+        // Drop the old binding before looking up
+        // the new one so a failed lookup can't
+        // hand out a symbol from an old version
+        self.bound = None;
+
+        let name = format!("{}\0", self.name);
+        let symbol = current
+            .lib
+            .get::<T>(name.as_bytes())
+            .map_err(|e| Error::Symbol(e, self.name.clone()))?
+            .into_raw();
+
+        self.bound = Some((current, symbol));
+        Ok(())
+    }
+}
+
+impl<T> fmt::Debug for ReloadableSymbol<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReloadableSymbol")
+            .field("name", &self.name)
+            .field("lib", &self.bound.as_ref().map(|(lib, _)| &lib.loaded_path))
+            .finish()
+    }
+}
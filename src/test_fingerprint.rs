@@ -0,0 +1,27 @@
+// Test library exporting a build fingerprint. Mirrors BuildFingerprint as this is built without
+// access to the crate, build.rs passes the values of the host.
+
+use std::ffi::c_char;
+
+#[repr(C)]
+pub struct BuildFingerprint {
+    rustc: *const c_char,
+    target: *const c_char,
+    profile: *const c_char,
+    api_version: u32,
+}
+
+unsafe impl Sync for BuildFingerprint {}
+
+#[no_mangle]
+pub static dr_build_fingerprint: BuildFingerprint = BuildFingerprint {
+    rustc: concat!(env!("DR_RUSTC_VERSION"), "\0").as_ptr() as *const c_char,
+    target: concat!(env!("DR_TARGET"), "\0").as_ptr() as *const c_char,
+    profile: concat!(env!("DR_PROFILE"), "\0").as_ptr() as *const c_char,
+    api_version: 1,
+};
+
+#[no_mangle]
+pub extern "C" fn fingerprint_value() -> i32 {
+    23
+}
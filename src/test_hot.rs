@@ -0,0 +1,11 @@
+// Test library for the hot_module macro
+
+#[no_mangle]
+pub extern "C" fn hot_add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+#[no_mangle]
+pub fn hot_greeting(name: &str) -> String {
+    format!("Hello {}", name)
+}
@@ -0,0 +1,76 @@
+// Test library for the plugin lifecycle exports and vtables. Mirrors PluginHost of the tests as
+// this is built without access to the crate. The vtable macros are included from the crate so
+// the layout hash is computed the way a separately built plugin does.
+
+use std::ffi::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicI32, AtomicPtr, Ordering};
+
+#[path = "plugin_decl.rs"]
+mod plugin_decl;
+pub use plugin_decl::{layout_hash, PluginDecl, PluginVTable};
+
+#[repr(C)]
+pub struct PluginHost {
+    live: AtomicI32,
+    fail_init: AtomicI32,
+    fail_restart: AtomicI32,
+}
+
+static HOST: AtomicPtr<PluginHost> = AtomicPtr::new(ptr::null_mut());
+static INITS: AtomicI32 = AtomicI32::new(0);
+
+#[no_mangle]
+pub unsafe extern "C" fn dr_plugin_init(host_ctx: *mut c_void) -> i32 {
+    let host = host_ctx as *mut PluginHost;
+
+    if host.is_null() {
+        return 0;
+    }
+
+    let fail = (*host).fail_init.load(Ordering::SeqCst);
+
+    // Only new versions fail so a rolled back version can start again
+    if fail != 0 && INITS.load(Ordering::SeqCst) == 0 {
+        return fail;
+    }
+
+    // Unless a version that was already started is made to fail too
+    let fail_restart = (*host).fail_restart.load(Ordering::SeqCst);
+
+    if fail_restart != 0 && INITS.load(Ordering::SeqCst) != 0 {
+        return fail_restart;
+    }
+
+    INITS.fetch_add(1, Ordering::SeqCst);
+    (*host).live.fetch_add(1, Ordering::SeqCst);
+    HOST.store(host, Ordering::SeqCst);
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dr_plugin_shutdown() {
+    let host = HOST.swap(ptr::null_mut(), Ordering::SeqCst);
+
+    if !host.is_null() {
+        (*host).live.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Spelled the same as TestApi in the tests
+crate::plugin_vtable! {
+    pub struct TestApi {
+        add: extern "C" fn(i32, i32) -> i32,
+        version: extern "C" fn() -> u32,
+    }
+}
+
+extern "C" fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+extern "C" fn version() -> u32 {
+    1
+}
+
+crate::declare_plugin!(TestApi, TestApi { add, version });
@@ -3,3 +3,7 @@
 pub fn shared_fun() -> i32 {
     48
 }
+
+// Signature tag of shared_fun, see RequiredSymbol
+#[export_name = "dr_sig__shared_fun__v1"]
+pub static SHARED_FUN_SIGNATURE: u8 = 0;
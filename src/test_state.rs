@@ -0,0 +1,49 @@
+// Test library for the state transfer protocol. Mirrors dynamic_reload::StateBuffer as this is
+// built without access to the crate.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const STATE_VERSION: u32 = 1;
+
+static COUNTER: AtomicU32 = AtomicU32::new(0);
+static SAVE_VERSION: AtomicU32 = AtomicU32::new(STATE_VERSION);
+
+#[repr(C)]
+pub struct StateBuffer {
+    version: u32,
+    data: *mut u8,
+    write: unsafe extern "C" fn(*mut u8, *const u8, usize),
+}
+
+#[no_mangle]
+pub fn set_counter(value: u32) {
+    COUNTER.store(value, Ordering::SeqCst);
+}
+
+#[no_mangle]
+pub fn get_counter() -> u32 {
+    COUNTER.load(Ordering::SeqCst)
+}
+
+#[no_mangle]
+pub fn set_save_version(version: u32) {
+    SAVE_VERSION.store(version, Ordering::SeqCst);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dr_save_state(state: *mut StateBuffer) {
+    let bytes = get_counter().to_le_bytes();
+    (*state).version = SAVE_VERSION.load(Ordering::SeqCst);
+    ((*state).write)((*state).data, bytes.as_ptr(), bytes.len());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dr_load_state(version: u32, data: *const u8, len: usize) -> u32 {
+    if version == STATE_VERSION && len == 4 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(std::slice::from_raw_parts(data, len));
+        set_counter(u32::from_le_bytes(bytes));
+    }
+
+    STATE_VERSION
+}
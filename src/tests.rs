@@ -1,4 +1,6 @@
 use super::*;
+use crate::debouncer::RawEvent;
+use crate::poll::PollBackend;
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -12,14 +14,40 @@ struct TestNotifyCallback {
     update_call_done: bool,
     after_update_done: bool,
     fail_update_done: bool,
+    fail_kept_lib: bool,
+    errors: Vec<Error>,
+    state_mismatch: Option<(u32, u32)>,
+    unchanged: bool,
+    reload_count: usize,
+    unload_timed_out: bool,
+    watch_error: bool,
+    removed: bool,
+    renamed: Option<PathBuf>,
+    added_count: usize,
 }
 
 impl TestNotifyCallback {
-    fn update_call(&mut self, state: UpdateState, _lib: Option<&Arc<Lib>>) {
+    fn update_call(&mut self, state: UpdateState, lib: Option<&Arc<Lib>>) {
         match state {
             UpdateState::Before => self.update_call_done = true,
-            UpdateState::After => self.after_update_done = true,
-            UpdateState::ReloadFailed(_) => self.fail_update_done = true,
+            UpdateState::After => {
+                self.after_update_done = true;
+                self.reload_count += 1;
+            }
+            UpdateState::ReloadFailed(err) => {
+                self.fail_update_done = true;
+                self.fail_kept_lib = lib.is_some();
+                self.errors.push(err);
+            }
+            UpdateState::StateMismatch(saved, expected) => {
+                self.state_mismatch = Some((saved, expected))
+            }
+            UpdateState::Unchanged => self.unchanged = true,
+            UpdateState::UnloadTimedOut => self.unload_timed_out = true,
+            UpdateState::WatchError(_) => self.watch_error = true,
+            UpdateState::Removed => self.removed = true,
+            UpdateState::Renamed(to) => self.renamed = Some(to),
+            UpdateState::Added => self.added_count += 1,
         }
 
         println!("Update state {:?}", self);
@@ -33,15 +61,42 @@ fn get_test_shared_lib() -> PathBuf {
     Path::new(&lib_path).join(DynamicReload::get_dynamiclib_name(lib_name))
 }
 
+// Copies one of the test libraries to a library only used by a single test
+fn copy_test_lib(src_name: &str, name: &str) -> PathBuf {
+    let src = get_test_shared_lib().with_file_name(DynamicReload::get_dynamiclib_name(src_name));
+    let dest = src.with_file_name(DynamicReload::get_dynamiclib_name(name));
+    fs::copy(&src, &dest).unwrap();
+    dest
+}
+
+// Copies test_shared into its own directory so
+// several tests can use libraries with the same
+// file name
+fn copy_test_lib_to_dir(dir: &str, name: &str) -> PathBuf {
+    let src = get_test_shared_lib();
+    let dest_dir = src.with_file_name(dir);
+    fs::create_dir_all(&dest_dir).unwrap();
+    let dest = dest_dir.join(DynamicReload::get_dynamiclib_name(name));
+    fs::copy(&src, &dest).unwrap();
+    dest
+}
+
+// Changes the content of a library without breaking it
+fn change_lib(path: &Path) {
+    let mut data = fs::read(path).unwrap();
+    data.push(0);
+    fs::write(path, data).unwrap();
+}
+
 #[test]
 fn test_search_paths_none() {
-    assert_eq!(DynamicReload::get_search_paths(None).len(), 0);
+    assert_eq!(DynamicReload::canonicalize_paths(Vec::new()).len(), 0);
 }
 
 #[test]
 fn test_search_paths_some() {
     assert_eq!(
-        DynamicReload::get_search_paths(Some(vec!["test", "test"])).len(),
+        DynamicReload::canonicalize_paths(vec![PathBuf::from("test"), PathBuf::from("test")]).len(),
         2
     );
 }
@@ -50,29 +105,85 @@ fn test_search_paths_some() {
 fn test_get_watcher() {
     let (tx, _) = channel();
     // We expect this to always work
-    assert!(DynamicReload::get_watcher(tx, Duration::from_secs(2)).is_some());
+    for mode in [WatchMode::Auto, WatchMode::Native, WatchMode::Poll] {
+        let watcher = DynamicReload::get_watcher(
+            tx.clone(),
+            Duration::from_secs(2),
+            mode,
+            Duration::from_secs(1),
+        );
+        assert!(watcher.is_ok());
+    }
 }
 
 #[test]
 fn test_get_temp_dir_fail() {
-    assert!(DynamicReload::get_temp_dir(Some("_no_such_dir")).is_none());
+    assert!(DynamicReload::get_temp_dir(Some(Path::new("_no_such_dir"))).is_err());
 }
 
 #[test]
 fn test_get_temp_dir_none() {
-    assert!(DynamicReload::get_temp_dir(None).is_none());
+    assert!(DynamicReload::get_temp_dir(None).unwrap().is_none());
 }
 
 #[test]
 fn test_get_temp_dir_ok() {
-    assert!(DynamicReload::get_temp_dir(Some("")).is_some());
+    assert!(DynamicReload::get_temp_dir(Some(Path::new("")))
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn test_builder_defaults() {
+    let dr = DynamicReload::builder().build().unwrap();
+    assert!(dr.shadow_dir.is_none());
+    assert!(dr.search_paths.is_empty());
+    assert_eq!(dr.timestamps, !cfg!(feature = "no-timestamps"));
+}
+
+#[test]
+fn test_builder_shadow_dir_fail() {
+    let res = DynamicReload::builder().shadow_dir("_no_such_dir").build();
+    assert!(matches!(res, Err(Error::ShadowDir(_, _))));
+}
+
+#[test]
+fn test_builder_runtime_options() {
+    let dr = DynamicReload::builder()
+        .search_paths(vec!["test", "test"])
+        .unload_policy(UnloadPolicy::Keep)
+        .timestamps(false)
+        .build()
+        .unwrap();
+    assert_eq!(dr.search_paths.len(), 2);
+    assert_eq!(dr.unload, UnloadPolicy::Keep);
+    assert_eq!(
+        DynamicReload::format_filename(
+            Path::new("shadow"),
+            Path::new("a/libfoo.so"),
+            false,
+            LibId(3),
+            1
+        ),
+        Path::new("shadow/3-1_libfoo.so")
+    );
+}
+
+#[test]
+fn test_new_lenient() {
+    let dr = DynamicReload::new(
+        None,
+        Some("_no_such_dir"),
+        Search::Default,
+        Duration::from_secs(2),
+    );
+    assert!(dr.shadow_dir.is_none());
 }
 
 #[test]
 fn test_is_file_fail() {
     assert!(
-        DynamicReload::is_file(&Path::new("haz_no_file_with_this_name").to_path_buf())
-            .is_none()
+        DynamicReload::is_file(&Path::new("haz_no_file_with_this_name").to_path_buf()).is_none()
     );
 }
 
@@ -121,16 +232,57 @@ fn test_add_library_fail() {
 
 #[test]
 fn test_add_shared_lib_ok() {
-    let mut dr = DynamicReload::new(None, None, Search::Default, Duration::from_secs(2));
+    let mut dr = DynamicReload::new(None, None, Search::Backwards, Duration::from_secs(2));
+    unsafe {
+        assert!(dr.add_library("test_shared", PlatformName::Yes).is_ok());
+    }
+}
+
+#[test]
+fn test_search_default_no_backwards() {
+    // test_shared lives in target/debug while the test exe is in target/debug/deps
+    let mut dr = DynamicReload::builder().build().unwrap();
+    unsafe {
+        assert!(matches!(
+            dr.add_library("test_shared", PlatformName::Yes),
+            Err(Error::Find(_))
+        ));
+    }
+}
+
+#[test]
+fn test_search_custom() {
+    let target_path = get_test_shared_lib();
+    let mut dr = DynamicReload::builder()
+        .search(Search::custom(move |name: &str| {
+            Some(target_path.with_file_name(name))
+        }))
+        .build()
+        .unwrap();
     unsafe {
         assert!(dr.add_library("test_shared", PlatformName::Yes).is_ok());
     }
 }
 
+#[test]
+fn test_search_custom_only() {
+    // Cargo.toml is in the current directory but the resolver is the only one asked
+    let mut dr = DynamicReload::builder()
+        .search(Search::custom(|_: &str| None))
+        .build()
+        .unwrap();
+    unsafe {
+        assert!(matches!(
+            dr.add_library("Cargo.toml", PlatformName::No),
+            Err(Error::Find(_))
+        ));
+    }
+}
+
 #[test]
 fn test_add_shared_lib_search_paths() {
     let mut dr = DynamicReload::new(
-        Some(vec!["../..", "../test"]),
+        Some(vec!["../..", "target/debug"]),
         None,
         Search::Default,
         Duration::from_secs(2),
@@ -174,7 +326,7 @@ fn test_add_shared_string_arg_ok() {
 #[test]
 fn test_add_shared_lib_search_paths_strings() {
     let path1 = "../..".to_owned();
-    let path2 = "../test".to_owned();
+    let path2 = "target/debug".to_owned();
     let mut dr = DynamicReload::new(
         Some(vec![&path1, &path2]),
         None,
@@ -189,23 +341,17 @@ fn test_add_shared_lib_search_paths_strings() {
 #[test]
 fn test_add_shared_update() {
     let mut notify_callback = TestNotifyCallback::default();
-    let target_path = get_test_shared_lib();
-
-    let mut dest_path = Path::new(&target_path).to_path_buf();
+    let target_path = copy_test_lib("test_shared", "test_update");
 
     let mut dr = DynamicReload::new(
-        None,
+        Some(vec!["target/debug"]),
         Some("target/debug"),
         Search::Default,
         Duration::from_secs(1),
     );
 
-    dest_path.set_file_name("test_file");
-
-    fs::copy(&target_path, &dest_path).unwrap();
-
     unsafe {
-        assert!(dr.add_library("test_shared", PlatformName::Yes).is_ok());
+        assert!(dr.add_library("test_update", PlatformName::Yes).is_ok());
     }
 
     for i in 0..10 {
@@ -214,7 +360,7 @@ fn test_add_shared_update() {
         }
 
         if i == 2 {
-            fs::copy(&dest_path, &target_path).unwrap();
+            change_lib(&target_path);
         }
 
         thread::sleep(Duration::from_millis(200));
@@ -225,6 +371,91 @@ fn test_add_shared_update() {
 }
 
 #[test]
+fn test_add_shared_update_unchanged() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let target_path = copy_test_lib("test_shared", "test_update_unchanged");
+
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .debounce(Duration::from_secs(1))
+        .build()
+        .unwrap();
+
+    unsafe {
+        assert!(dr
+            .add_library("test_update_unchanged", PlatformName::Yes)
+            .is_ok());
+    }
+
+    for i in 0..10 {
+        unsafe {
+            dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+        }
+
+        if i == 2 {
+            // Same content written again
+            fs::write(&target_path, fs::read(&target_path).unwrap()).unwrap();
+        }
+
+        thread::sleep(Duration::from_millis(200));
+    }
+
+    assert!(notify_callback.unchanged);
+    assert!(!notify_callback.update_call_done);
+    assert!(!notify_callback.after_update_done);
+}
+
+#[test]
+fn test_reload_without_timestamps() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let target_path = copy_test_lib("test_shared", "test_reload_no_timestamps");
+
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .timestamps(false)
+        .unload_policy(UnloadPolicy::Unload)
+        .build()
+        .unwrap();
+
+    let lib = unsafe {
+        dr.add_library("test_reload_no_timestamps", PlatformName::Yes)
+            .unwrap()
+    };
+    let first_path = lib.loaded_path.clone();
+
+    // The old version stays loaded while the new
+    // one is copied and opened
+    for _ in 0..2 {
+        change_lib(&target_path);
+
+        unsafe {
+            dr.reload_lib(
+                0,
+                &target_path,
+                &TestNotifyCallback::update_call,
+                &mut notify_callback,
+            );
+        }
+    }
+
+    assert_eq!(notify_callback.reload_count, 2);
+    assert!(!notify_callback.fail_update_done);
+
+    let current = dr.libs[0].lib.clone();
+    assert_eq!(current.generation, 2);
+    assert_ne!(current.loaded_path, first_path);
+
+    let fun: Symbol<fn() -> i32> = unsafe { lib.lib.get(b"shared_fun\0") }.unwrap();
+    assert_eq!(fun(), 48);
+
+    let fun: Symbol<fn() -> i32> = unsafe { current.lib.get(b"shared_fun\0") }.unwrap();
+    assert_eq!(fun(), 48);
+}
+
+#[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_add_shared_update_fail_after() {
     let mut notify_callback = TestNotifyCallback::default();
     let target_path = get_test_shared_lib();
@@ -268,11 +499,13 @@ fn test_add_shared_update_fail_after() {
     assert_eq!(notify_callback.update_call_done, true);
     assert_eq!(notify_callback.after_update_done, false);
     assert_eq!(notify_callback.fail_update_done, true);
+    assert!(notify_callback.fail_kept_lib);
+    assert_eq!(dr.libs.len(), 1);
 }
 
 #[test]
 fn test_lib_equals_true() {
-    let mut dr = DynamicReload::new(None, None, Search::Default, Duration::from_secs(2));
+    let mut dr = DynamicReload::new(None, None, Search::Backwards, Duration::from_secs(2));
     let lib = unsafe { dr.add_library("test_shared", PlatformName::Yes).unwrap() };
     let lib2 = lib.clone();
     assert!(lib == lib2);
@@ -301,3 +534,2087 @@ fn test_lib_equals_false() {
 
     assert!(lib0 != lib1);
 }
+
+#[test]
+fn test_remove_library() {
+    let mut dr = DynamicReload::builder()
+        .shadow_dir("target/debug")
+        .search(Search::Backwards)
+        .unload_policy(UnloadPolicy::Unload)
+        .build()
+        .unwrap();
+    let lib = unsafe { dr.add_library("test_shared", PlatformName::Yes).unwrap() };
+    let loaded_path = lib.loaded_path.clone();
+    assert!(loaded_path.exists());
+
+    assert_eq!(dr.remove_library(&lib).unwrap(), RemoveState::Unloaded);
+    assert!(dr.libs.is_empty());
+    assert!(!loaded_path.exists());
+
+    // Not tracked anymore
+    assert!(matches!(dr.remove_library(&lib), Err(Error::Find(_))));
+}
+
+#[test]
+fn test_remove_library_still_referenced() {
+    let mut dr = DynamicReload::builder()
+        .search(Search::Backwards)
+        .unload_policy(UnloadPolicy::Unload)
+        .build()
+        .unwrap();
+    let lib = unsafe { dr.add_library("test_shared", PlatformName::Yes).unwrap() };
+    let _other = lib.clone();
+
+    assert_eq!(
+        dr.remove_library(&lib).unwrap(),
+        RemoveState::StillReferenced(1)
+    );
+}
+
+#[test]
+fn test_remove_library_by_name() {
+    let mut dr = DynamicReload::builder()
+        .shadow_dir("target/debug")
+        .search(Search::Backwards)
+        .unload_policy(UnloadPolicy::Keep)
+        .build()
+        .unwrap();
+    unsafe {
+        dr.add_library("test_shared", PlatformName::Yes).unwrap();
+    }
+
+    assert!(dr
+        .remove_library_by_name("no_such_lib", PlatformName::Yes)
+        .is_err());
+    assert_eq!(
+        dr.remove_library_by_name("test_shared", PlatformName::Yes)
+            .unwrap(),
+        RemoveState::Kept
+    );
+    assert!(dr.libs.is_empty());
+}
+
+#[test]
+fn test_remove_library_same_name() {
+    let path_a = copy_test_lib_to_dir("test_remove_same_a", "test_remove_same");
+    let path_b = copy_test_lib_to_dir("test_remove_same_b", "test_remove_same");
+
+    let mut dr = DynamicReload::builder()
+        .search_path(path_a.parent().unwrap())
+        .shadow_dir("target/debug")
+        .unload_policy(UnloadPolicy::Unload)
+        .build()
+        .unwrap();
+    let lib_a = unsafe {
+        dr.add_library("test_remove_same", PlatformName::Yes)
+            .unwrap()
+    };
+
+    dr.search_paths =
+        DynamicReload::canonicalize_paths(vec![path_b.parent().unwrap().to_path_buf()]);
+    let lib_b = unsafe {
+        dr.add_library("test_remove_same", PlatformName::Yes)
+            .unwrap()
+    };
+    let loaded_b = lib_b.loaded_path.clone();
+    assert_ne!(lib_a.loaded_path, loaded_b);
+
+    // Only the copies of a are deleted
+    assert_eq!(dr.remove_library(&lib_a).unwrap(), RemoveState::Unloaded);
+    assert!(!lib_a.loaded_path.exists());
+    assert!(loaded_b.exists());
+
+    let fun: Symbol<fn() -> i32> = unsafe { lib_b.lib.get(b"shared_fun\0") }.unwrap();
+    assert_eq!(fun(), 48);
+}
+
+#[test]
+fn test_shadow_copies() {
+    let dr = DynamicReload::builder()
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    let shadow_dir = dr.shadow_dir.as_ref().unwrap().path();
+
+    let names = [
+        "3-0_libfoo.so",
+        "123-3-1_libfoo.so",
+        "4-0_libfoo.so",
+        "123-4-0_libfoo.so",
+        "abc-3-0_libfoo.so",
+        "3-0_libfoo.so.1",
+        "libfoo.so",
+    ];
+
+    for name in names.iter() {
+        fs::write(shadow_dir.join(name), b"").unwrap();
+    }
+
+    let mut copies = dr.shadow_copies(Path::new("some/dir/libfoo.so"), LibId(3));
+    copies.sort();
+
+    assert_eq!(
+        copies,
+        vec![
+            shadow_dir.join("123-3-1_libfoo.so"),
+            shadow_dir.join("3-0_libfoo.so")
+        ]
+    );
+}
+
+#[test]
+fn test_symbol() {
+    let mut dr = DynamicReload::builder()
+        .search(Search::Backwards)
+        .unload_policy(UnloadPolicy::Unload)
+        .build()
+        .unwrap();
+    let lib = unsafe { dr.add_library("test_shared", PlatformName::Yes).unwrap() };
+
+    let mut fun = unsafe { dr.symbol::<fn() -> i32>(&lib, "shared_fun").unwrap() };
+    assert_eq!(fun.name(), "shared_fun");
+    assert_eq!((fun.get().unwrap())(), 48);
+
+    assert!(matches!(
+        unsafe { dr.symbol::<fn() -> i32>(&lib, "no_such_fun") },
+        Err(Error::Symbol(_, _))
+    ));
+
+    dr.remove_library(&lib).unwrap();
+    assert!(matches!(fun.get(), Err(Error::Removed(_))));
+}
+
+#[test]
+fn test_symbol_rebind_after_reload() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let original_path = copy_test_lib("test_shared", "test_rebind");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .unload_policy(UnloadPolicy::Unload)
+        .build()
+        .unwrap();
+    let lib = unsafe { dr.add_library("test_rebind", PlatformName::Yes).unwrap() };
+
+    let mut fun = unsafe { dr.symbol::<fn() -> i32>(&lib, "shared_fun").unwrap() };
+    assert_eq!((fun.get().unwrap())(), 48);
+
+    change_lib(&original_path);
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(notify_callback.after_update_done);
+    assert!(Arc::strong_count(&lib) > 1);
+
+    // Rebinding releases the old version
+    assert_eq!((fun.get().unwrap())(), 48);
+    assert_eq!(Arc::strong_count(&lib), 1);
+}
+
+fn reload_test_state(name: &str, set_save_version: Option<u32>) -> (u32, TestNotifyCallback) {
+    let mut notify_callback = TestNotifyCallback::default();
+    let original_path = copy_test_lib("test_state", name);
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    let lib = unsafe { dr.add_library(name, PlatformName::Yes).unwrap() };
+
+    let mut set_counter = unsafe { dr.symbol::<fn(u32)>(&lib, "set_counter").unwrap() };
+    let mut get_counter = unsafe { dr.symbol::<fn() -> u32>(&lib, "get_counter").unwrap() };
+    (set_counter.get().unwrap())(42);
+
+    if let Some(version) = set_save_version {
+        let set_version: Symbol<fn(u32)> = unsafe { lib.lib.get(b"set_save_version\0").unwrap() };
+        set_version(version);
+    }
+
+    drop(lib);
+    change_lib(&original_path);
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(notify_callback.after_update_done);
+    let counter = (get_counter.get().unwrap())();
+    (counter, notify_callback)
+}
+
+#[test]
+fn test_state_transfer() {
+    let (counter, notify_callback) = reload_test_state("test_state_transfer", None);
+    assert_eq!(counter, 42);
+    assert_eq!(notify_callback.state_mismatch, None);
+}
+
+#[test]
+fn test_state_transfer_mismatch() {
+    let (counter, notify_callback) = reload_test_state("test_state_mismatch", Some(2));
+    assert_eq!(counter, 0);
+    assert_eq!(notify_callback.state_mismatch, Some((2, 1)));
+}
+
+// Mirrors PluginHost in test_plugin.rs
+#[repr(C)]
+#[derive(Default)]
+struct PluginHost {
+    live: std::sync::atomic::AtomicI32,
+    fail_init: std::sync::atomic::AtomicI32,
+    fail_restart: std::sync::atomic::AtomicI32,
+}
+
+impl PluginHost {
+    fn live(&self) -> i32 {
+        self.live.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    fn fail_init(&self, code: i32) {
+        self.fail_init
+            .store(code, std::sync::atomic::Ordering::SeqCst)
+    }
+
+    fn fail_restart(&self, code: i32) {
+        self.fail_restart
+            .store(code, std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+fn plugin_reload(name: &str, host: &PluginHost) -> DynamicReload {
+    copy_test_lib("test_plugin", name);
+    unsafe {
+        DynamicReload::builder()
+            .search_path("target/debug")
+            .shadow_dir("target/debug")
+            .host_context(host as *const PluginHost as *mut std::ffi::c_void)
+            .build()
+            .unwrap()
+    }
+}
+
+#[test]
+fn test_plugin_lifecycle() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let host = PluginHost::default();
+    let mut dr = plugin_reload("test_plugin_lifecycle", &host);
+    let lib = unsafe {
+        dr.add_library("test_plugin_lifecycle", PlatformName::Yes)
+            .unwrap()
+    };
+    let original_path = lib.original_path.clone().unwrap();
+    drop(lib);
+
+    assert_eq!(host.live(), 1);
+
+    // The old version is shut down before the new one starts
+    change_lib(&original_path);
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(notify_callback.after_update_done);
+    assert_eq!(host.live(), 1);
+
+    let lib = dr.libs[0].lib.clone();
+    dr.remove_library(&lib).unwrap();
+
+    assert_eq!(host.live(), 0);
+}
+
+#[test]
+fn test_plugin_init_failure() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let host = PluginHost::default();
+    let mut dr = plugin_reload("test_plugin_init_failure", &host);
+
+    host.fail_init(7);
+
+    assert!(matches!(
+        unsafe { dr.add_library("test_plugin_init_failure", PlatformName::Yes) },
+        Err(Error::PluginInit(7, _))
+    ));
+    assert_eq!(host.live(), 0);
+
+    host.fail_init(0);
+
+    let lib = unsafe {
+        dr.add_library("test_plugin_init_failure", PlatformName::Yes)
+            .unwrap()
+    };
+    let original_path = lib.original_path.clone().unwrap();
+    drop(lib);
+
+    // A new version failing to start rolls back to the old one
+    host.fail_init(7);
+    change_lib(&original_path);
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(notify_callback.fail_update_done);
+    assert!(notify_callback.fail_kept_lib);
+    assert!(!notify_callback.after_update_done);
+    assert_eq!(dr.libs[0].lib.generation, 0);
+    assert_eq!(host.live(), 1);
+
+    // If the old version can't start again either the library is dropped
+    let mut notify_callback = TestNotifyCallback::default();
+    host.fail_restart(9);
+    change_lib(&original_path);
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(!notify_callback.fail_kept_lib);
+    assert!(matches!(
+        notify_callback.errors.as_slice(),
+        [Error::PluginInit(7, _), Error::PluginInit(9, _)]
+    ));
+    assert!(dr.libs.is_empty());
+    assert_eq!(host.live(), 0);
+}
+
+// Spelled the same as TestApi in test_plugin.rs,
+// which is built separately, so the hashes have to
+// agree across crates
+crate::plugin_vtable! {
+    struct TestApi {
+        add: extern "C" fn(i32, i32) -> i32,
+        version: extern "C" fn() -> u32,
+    }
+}
+
+// Stands in for a changed version of TestApi
+crate::plugin_vtable! {
+    struct OtherApi {
+        add: extern "C" fn(i32, i32) -> i32,
+    }
+}
+
+#[test]
+fn test_add_plugin() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let original_path = copy_test_lib("test_plugin", "test_add_plugin");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    let mut api = unsafe {
+        dr.add_plugin::<TestApi>("test_add_plugin", PlatformName::Yes)
+            .unwrap()
+    };
+
+    assert_eq!((api.get().unwrap().add)(1, 2), 3);
+    assert_eq!((api.get().unwrap().version)(), 1);
+
+    // The vtable is rebound after a reload
+    change_lib(&original_path);
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(notify_callback.after_update_done);
+    assert_eq!((api.get().unwrap().add)(2, 2), 4);
+    assert_eq!(api.lib().unwrap().generation, 1);
+
+    // A new version without the vtable is refused
+    fs::copy(get_test_shared_lib(), &original_path).unwrap();
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(notify_callback.fail_update_done);
+    assert_eq!(dr.libs[0].lib.generation, 1);
+    assert_eq!((api.get().unwrap().add)(3, 2), 5);
+}
+
+#[test]
+fn test_add_plugin_layout_mismatch() {
+    copy_test_lib("test_plugin", "test_plugin_mismatch");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+
+    let result = unsafe { dr.add_plugin::<OtherApi>("test_plugin_mismatch", PlatformName::Yes) };
+
+    match result {
+        Err(Error::PluginLayout(expected, found, _)) => {
+            assert_eq!(expected, OtherApi::LAYOUT_HASH);
+            assert_eq!(found, TestApi::LAYOUT_HASH);
+        }
+        other => panic!("expected a layout mismatch, got {:?}", other),
+    }
+
+    assert!(dr.libs.is_empty());
+}
+
+crate::plugin_vtable! {
+    struct MacroApi {
+        add: extern "C" fn(i32, i32) -> i32,
+    }
+}
+
+crate::plugin_vtable! {
+    struct MacroApiChanged {
+        add: extern "C" fn(i64, i64) -> i64,
+    }
+}
+
+mod declared {
+    extern "C" fn add(a: i32, b: i32) -> i32 {
+        a + b
+    }
+
+    crate::declare_plugin!(super::MacroApi, super::MacroApi { add });
+}
+
+#[test]
+fn test_plugin_macros() {
+    assert_ne!(MacroApi::LAYOUT_HASH, MacroApiChanged::LAYOUT_HASH);
+    assert_eq!(
+        declared::dr_plugin_vtable.layout_hash,
+        MacroApi::LAYOUT_HASH
+    );
+    assert_eq!((declared::dr_plugin_vtable.vtable.add)(1, 2), 3);
+}
+
+#[crate::hot_module(dylib = "test_hot_module")]
+mod hot {
+    extern "C" {
+        pub fn hot_add(a: i32, b: i32) -> i32;
+
+        #[link_name = "hot_add"]
+        pub fn hot_sum(a: i32, b: i32) -> i32;
+    }
+
+    extern "Rust" {
+        pub fn hot_greeting(name: &str) -> String;
+    }
+}
+
+#[test]
+fn test_hot_module() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let original_path = copy_test_lib("test_hot", "test_hot_module");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+
+    assert!(hot::HOT_LIB.current().is_none());
+
+    unsafe { hot::HOT_LIB.load(&mut dr).unwrap() };
+
+    unsafe {
+        assert_eq!(hot::hot_add(1, 2), 3);
+        assert_eq!(hot::hot_greeting("hot"), "Hello hot");
+        assert_eq!(hot::hot_sum(2, 3), 5);
+
+        // Calling again uses the cached function
+        assert_eq!(hot::hot_add(2, 3), 5);
+    }
+
+    // The functions follow the reloads
+    change_lib(&original_path);
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(notify_callback.after_update_done);
+    assert_eq!(hot::HOT_LIB.current().unwrap().generation, 1);
+    assert_eq!(unsafe { hot::hot_add(2, 2) }, 4);
+
+    // The cached function only holds the version
+    // weakly
+    assert_eq!(Arc::strong_count(&hot::HOT_LIB.current().unwrap()), 3);
+}
+
+#[test]
+fn test_lib_equals_false_no_shadow_dir() {
+    let mut dr = DynamicReload::builder()
+        .search(Search::Backwards)
+        .build()
+        .unwrap();
+    let lib0 = unsafe { dr.add_library("test_shared", PlatformName::Yes).unwrap() };
+    let lib1 = unsafe { dr.add_library("test_state", PlatformName::Yes).unwrap() };
+
+    assert!(lib0 != lib1);
+    assert!(lib0.id != lib1.id);
+}
+
+#[test]
+fn test_lib_id_and_generation() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let original_path = copy_test_lib("test_shared", "test_generation");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .unload_policy(UnloadPolicy::Unload)
+        .build()
+        .unwrap();
+    let lib = unsafe {
+        dr.add_library("test_generation", PlatformName::Yes)
+            .unwrap()
+    };
+    assert_eq!(lib.generation, 0);
+
+    change_lib(&original_path);
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    let reloaded = dr.libs[0].lib.clone();
+    assert_eq!(reloaded.id, lib.id);
+    assert_eq!(reloaded.generation, 1);
+    assert!(reloaded == lib);
+
+    // An old version still identifies the library
+    assert_eq!(
+        dr.remove_library(&lib).unwrap(),
+        RemoveState::StillReferenced(1)
+    );
+}
+
+#[test]
+fn test_reload_failed_keeps_old_version() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let target_path = get_test_shared_lib();
+    let test_file = DynamicReload::get_dynamiclib_name("test_rollback");
+    let dest_path = target_path.with_file_name(&test_file);
+    fs::copy(&target_path, &dest_path).unwrap();
+
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    let lib = unsafe { dr.add_library(&test_file, PlatformName::No).unwrap() };
+    let mut fun = unsafe { dr.symbol::<fn() -> i32>(&lib, "shared_fun").unwrap() };
+
+    // Broken "lib"
+    fs::copy("Cargo.toml", &dest_path).unwrap();
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &dest_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(notify_callback.fail_update_done);
+    assert!(notify_callback.fail_kept_lib);
+    assert!(Arc::ptr_eq(&dr.libs[0].lib, &lib));
+    assert_eq!((fun.get().unwrap())(), 48);
+}
+
+#[test]
+fn test_reload_unchanged() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let original_path = copy_test_lib("test_shared", "test_unchanged");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    let lib = unsafe { dr.add_library("test_unchanged", PlatformName::Yes).unwrap() };
+    assert_eq!(
+        lib.content_hash,
+        DynamicReload::hash_file(&original_path).unwrap()
+    );
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(notify_callback.unchanged);
+    assert!(!notify_callback.update_call_done);
+    assert!(Arc::ptr_eq(&dr.libs[0].lib, &lib));
+}
+
+#[test]
+fn test_update_coalesces_events() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let path_a = copy_test_lib("test_shared", "test_coalesce_a");
+    let path_b = copy_test_lib("test_shared", "test_coalesce_b");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    let lib_a = unsafe {
+        dr.add_library("test_coalesce_a", PlatformName::Yes)
+            .unwrap()
+    };
+    let lib_b = unsafe {
+        dr.add_library("test_coalesce_b", PlatformName::Yes)
+            .unwrap()
+    };
+
+    // Feed the events directly instead of waiting for the watcher
+    let (tx, rx) = channel();
+    dr.watch_recv = rx;
+
+    change_lib(&path_a);
+    change_lib(&path_b);
+
+    tx.send(WatchEvent::Changed(path_b.clone())).unwrap();
+    tx.send(WatchEvent::Changed(path_a.clone())).unwrap();
+    tx.send(WatchEvent::Changed(path_b.clone())).unwrap();
+    tx.send(WatchEvent::Changed(path_b)).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.reload_count, 2);
+    assert!(!notify_callback.unchanged);
+    assert_eq!(dr.libs[0].lib.id, lib_a.id);
+    assert_eq!(dr.libs[0].lib.generation, 1);
+    assert_eq!(dr.libs[1].lib.id, lib_b.id);
+    assert_eq!(dr.libs[1].lib.generation, 1);
+}
+
+#[test]
+fn test_update_matches_full_path() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let path_a = copy_test_lib_to_dir("test_match_exact_a", "test_match_exact");
+    let path_b = copy_test_lib_to_dir("test_match_exact_b", "test_match_exact");
+    let path_c = copy_test_lib_to_dir("test_match_exact_c", "test_match_exact");
+    let mut dr = DynamicReload::builder()
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    let lib_a = unsafe {
+        dr.add_library(path_a.to_str().unwrap(), PlatformName::No)
+            .unwrap()
+    };
+    let lib_b = unsafe {
+        dr.add_library(path_b.to_str().unwrap(), PlatformName::No)
+            .unwrap()
+    };
+
+    let (tx, rx) = channel();
+    dr.watch_recv = rx;
+
+    change_lib(&path_a);
+    change_lib(&path_b);
+    change_lib(&path_c);
+
+    // Not tracked even though the file name is the same
+    tx.send(WatchEvent::Changed(path_c)).unwrap();
+    tx.send(WatchEvent::Changed(path_a)).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.reload_count, 1);
+    assert_eq!(dr.libs[0].lib.id, lib_a.id);
+    assert_eq!(dr.libs[0].lib.generation, 1);
+    assert_eq!(dr.libs[1].lib.id, lib_b.id);
+    assert_eq!(dr.libs[1].lib.generation, 0);
+}
+
+#[test]
+fn test_update_matches_file_name() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let path_a = copy_test_lib_to_dir("test_match_name_a", "test_match_name");
+    let path_b = copy_test_lib_to_dir("test_match_name_b", "test_match_name");
+    let mut dr = DynamicReload::builder()
+        .shadow_dir("target/debug")
+        .path_match(PathMatch::FileName)
+        .build()
+        .unwrap();
+    unsafe {
+        dr.add_library(path_a.to_str().unwrap(), PlatformName::No)
+            .unwrap();
+        dr.add_library(path_b.to_str().unwrap(), PlatformName::No)
+            .unwrap();
+    }
+
+    let (tx, rx) = channel();
+    dr.watch_recv = rx;
+
+    change_lib(&path_a);
+    tx.send(WatchEvent::Changed(path_a)).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.reload_count, 2);
+    assert_eq!(dr.libs[0].lib.generation, 1);
+    assert_eq!(dr.libs[1].lib.generation, 1);
+}
+
+#[test]
+fn test_same_file_relative_path() {
+    let path = copy_test_lib("test_shared", "test_same_file");
+    let relative = Path::new("target/debug").join(path.file_name().unwrap());
+
+    assert!(DynamicReload::same_file(&path, &relative));
+    assert!(!DynamicReload::same_file(&path, &get_test_shared_lib()));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_same_file_hard_link() {
+    let path = copy_test_lib("test_shared", "test_same_file_link");
+    let link = path.with_file_name("test_same_file_link_other");
+    let _ = fs::remove_file(&link);
+    fs::hard_link(&path, &link).unwrap();
+
+    assert!(DynamicReload::same_file(&path, &link));
+}
+
+// Points ```link``` at ```target``` (relative to the directory of the link)
+#[cfg(unix)]
+fn symlink(target: &str, link: &Path) {
+    let _ = fs::remove_file(link);
+    std::os::unix::fs::symlink(target, link).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_link_chain() {
+    let path = copy_test_lib_to_dir("test_link_chain/v1", "test_link_chain");
+    let dir = path.parent().unwrap().parent().unwrap().to_path_buf();
+    let name = path.file_name().unwrap().to_str().unwrap();
+    fs::create_dir_all(dir.join("current")).unwrap();
+    let link = dir.join("current").join(name);
+    let latest = dir.join(name);
+    symlink(&format!("../v1/{}", name), &link);
+    symlink(&format!("current/{}", name), &latest);
+
+    let links = DynamicReload::link_chain(&latest);
+
+    assert_eq!(links.len(), 2);
+    assert_eq!(links[0], DynamicReload::canonical_path(&dir).join(name));
+    assert_eq!(
+        links[1],
+        DynamicReload::canonical_path(link.parent().unwrap()).join(name)
+    );
+    assert!(DynamicReload::link_chain(&path).is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_update_follows_symlink() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let v1 = copy_test_lib_to_dir("test_symlink/v1", "test_symlink");
+    let v2 = copy_test_lib_to_dir("test_symlink/v2", "test_symlink");
+    let name = v1.file_name().unwrap().to_str().unwrap().to_string();
+    let current = v1.parent().unwrap().with_file_name("current");
+    fs::create_dir_all(&current).unwrap();
+    let link = current.join(&name);
+    symlink(&format!("../v1/{}", name), &link);
+    change_lib(&v2);
+
+    let mut dr = DynamicReload::builder()
+        .search_path(&current)
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    let lib = unsafe { dr.add_library("test_symlink", PlatformName::Yes).unwrap() };
+
+    assert_eq!(lib.original_path, Some(v1.canonicalize().unwrap()));
+    assert_eq!(lib.link_path, Some(DynamicReload::link_location(&link)));
+
+    // Both the link and the target are watched
+    let dirs = DynamicReload::watch_dirs(lib.original_path.as_ref(), &lib.links);
+    assert_eq!(dirs.len(), 2);
+
+    let (tx, rx) = channel();
+    dr.watch_recv = rx;
+
+    // Repointing the link loads the new target
+    symlink(&format!("../v2/{}", name), &link);
+    tx.send(WatchEvent::Changed(link.clone())).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.reload_count, 1);
+    assert_eq!(
+        dr.libs[0].lib.original_path,
+        Some(v2.canonicalize().unwrap())
+    );
+
+    // The old target is no longer tracked but the new one is
+    change_lib(&v1);
+    change_lib(&v2);
+    tx.send(WatchEvent::Changed(v1)).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.reload_count, 1);
+
+    tx.send(WatchEvent::Changed(v2.clone())).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.reload_count, 2);
+    assert_eq!(dr.libs[0].lib.link_path, lib.link_path);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_update_follows_symlink_poll_mode() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let v1 = copy_test_lib_to_dir("test_symlink_poll/v1", "test_symlink_poll");
+    let v2 = copy_test_lib_to_dir("test_symlink_poll/v2", "test_symlink_poll");
+    let name = v1.file_name().unwrap().to_str().unwrap().to_string();
+    let current = v1.parent().unwrap().with_file_name("current");
+    fs::create_dir_all(&current).unwrap();
+    let link = current.join(&name);
+    symlink(&format!("../v1/{}", name), &link);
+    change_lib(&v2);
+
+    let mut dr = DynamicReload::builder()
+        .search_path(&current)
+        .shadow_dir("target/debug")
+        .watch_mode(WatchMode::Poll)
+        .poll_interval(Duration::from_millis(50))
+        .debounce(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    unsafe {
+        dr.add_library("test_symlink_poll", PlatformName::Yes)
+            .unwrap();
+    }
+
+    // Repoint the link in one step so the poller
+    // never sees it missing
+    let tmp = current.join("next");
+    symlink(&format!("../v2/{}", name), &tmp);
+    fs::rename(&tmp, &link).unwrap();
+
+    let start = std::time::Instant::now();
+    while notify_callback.reload_count == 0 && start.elapsed() < Duration::from_secs(5) {
+        unsafe {
+            dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    assert_eq!(notify_callback.reload_count, 1);
+    assert_eq!(
+        dr.libs[0].lib.original_path,
+        Some(v2.canonicalize().unwrap())
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_reload_in_place_new_file() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let original_path = copy_test_lib("test_shared", "test_in_place_new");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .build()
+        .unwrap();
+    let lib = unsafe {
+        dr.add_library("test_in_place_new", PlatformName::Yes)
+            .unwrap()
+    };
+    assert!(lib.original_path.is_some());
+    assert!(unsafe { lib.lib.get::<fn() -> u32>(b"get_counter\0").is_err() });
+
+    // Linkers write a new file instead of overwriting the old one
+    let tmp_path = copy_test_lib("test_state", "test_in_place_new_tmp");
+    fs::rename(&tmp_path, &original_path).unwrap();
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    let reloaded = dr.libs[0].lib.clone();
+    assert!(notify_callback.after_update_done);
+    assert_eq!(reloaded.generation, 1);
+    assert_eq!(reloaded.loaded_path, lib.loaded_path);
+    assert!(unsafe { reloaded.lib.get::<fn() -> u32>(b"get_counter\0").is_ok() });
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_reload_in_place_old_version_mapped() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let original_path = copy_test_lib("test_shared", "test_in_place_mapped");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .unload_policy(UnloadPolicy::Unload)
+        .build()
+        .unwrap();
+    let lib = unsafe {
+        dr.add_library("test_in_place_mapped", PlatformName::Yes)
+            .unwrap()
+    };
+
+    // Open the first version again through its descriptor so it
+    // stays mapped after DynamicReload closes it
+    let canonical = original_path.canonicalize().unwrap();
+    let fd_path = fs::read_dir("/proc/self/fd")
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|fd| fs::read_link(fd).ok().as_ref() == Some(&canonical))
+        .unwrap();
+    let mapped = unsafe { Library::new(&fd_path) }.unwrap();
+
+    let mut replace_with = |name: &str| {
+        let tmp_path = copy_test_lib(name, "test_in_place_mapped_tmp");
+        change_lib(&tmp_path);
+        fs::rename(&tmp_path, &original_path).unwrap();
+
+        unsafe {
+            dr.reload_lib(
+                0,
+                &original_path,
+                &TestNotifyCallback::update_call,
+                &mut notify_callback,
+            );
+        }
+    };
+
+    replace_with("test_shared");
+    drop(lib);
+
+    // The descriptor of the first version is free again and
+    // likely to be reused by the next one
+    replace_with("test_hot");
+
+    let reloaded = dr.libs[0].lib.clone();
+    assert_eq!(reloaded.generation, 2);
+    assert!(unsafe { reloaded.lib.get::<fn() -> i32>(b"hot_add\0").is_ok() });
+    drop(mapped);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_reload_in_place_same_file() {
+    use std::io::Write;
+
+    let mut notify_callback = TestNotifyCallback::default();
+    let original_path = copy_test_lib("test_shared", "test_in_place_same");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .build()
+        .unwrap();
+    let lib = unsafe {
+        dr.add_library("test_in_place_same", PlatformName::Yes)
+            .unwrap()
+    };
+
+    // Append instead of rewriting so the loaded file stays intact
+    fs::OpenOptions::new()
+        .append(true)
+        .open(&original_path)
+        .unwrap()
+        .write_all(&[0])
+        .unwrap();
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(notify_callback.fail_update_done);
+    assert!(notify_callback.fail_kept_lib);
+    assert!(dr.libs[0].lib == lib);
+    assert_eq!(dr.libs[0].lib.generation, 0);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_apply_pending_sends_events() {
+    use futures::{executor::block_on, StreamExt};
+
+    let original_path = copy_test_lib("test_shared", "test_apply_pending");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    let lib = unsafe {
+        dr.add_library("test_apply_pending", PlatformName::Yes)
+            .unwrap()
+    };
+    let events = dr.events();
+
+    let (tx, rx) = channel();
+    dr.watch_recv = rx;
+
+    change_lib(&original_path);
+    tx.send(WatchEvent::Changed(original_path)).unwrap();
+
+    // Runs the reload on a thread of its own like
+    // the blocking pool of a runtime would
+    let dr = block_on(unsafe {
+        dr.apply_pending(|work| {
+            let (tx, rx) = futures::channel::oneshot::channel();
+            thread::spawn(move || {
+                let _ = tx.send(work());
+            });
+            async { rx.await.unwrap() }
+        })
+    });
+    drop(dr);
+
+    let events = block_on(events.collect::<Vec<_>>());
+    assert_eq!(events.len(), 2);
+    assert!(matches!(events[0].state, UpdateState::Before));
+    assert!(matches!(events[1].state, UpdateState::After));
+    assert!(events[1].lib.as_ref().unwrap() == &lib);
+    assert_eq!(events[1].lib.as_ref().unwrap().generation, 1);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_apply_pending_waits_for_watcher() {
+    use futures::{executor::block_on, StreamExt};
+
+    let original_path = copy_test_lib("test_shared", "test_apply_pending_wait");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .debounce(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    unsafe {
+        dr.add_library("test_apply_pending_wait", PlatformName::Yes)
+            .unwrap();
+    }
+    let events = dr.events();
+
+    let t = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        change_lib(&original_path);
+    });
+
+    let dr = block_on(unsafe { dr.apply_pending(|work| async move { work() }) });
+    t.join().unwrap();
+    drop(dr);
+
+    assert!(!block_on(events.collect::<Vec<_>>()).is_empty());
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_apply_pending_is_send() {
+    fn assert_send<T: Send>(_: T) {}
+
+    let dr = DynamicReload::builder().build().unwrap();
+    assert_send(unsafe { dr.apply_pending(|work| async move { work() }) });
+}
+
+#[test]
+fn test_lib_handle() {
+    let original_path = copy_test_lib("test_shared", "test_lib_handle");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    let lib = unsafe {
+        dr.add_library("test_lib_handle", PlatformName::Yes)
+            .unwrap()
+    };
+    let handle = dr.handle(&lib).unwrap();
+    assert_eq!(handle.id(), lib.id);
+    assert!(Arc::ptr_eq(&handle.current().unwrap(), &lib));
+
+    change_lib(&original_path);
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut TestNotifyCallback::default(),
+        );
+    }
+
+    assert_eq!(handle.current().unwrap().generation, 1);
+
+    dr.remove_library(&lib).unwrap();
+    assert!(handle.current().is_none());
+    assert!(dr.handle(&lib).is_none());
+}
+
+#[test]
+fn test_shared_dynamic_reload() {
+    let original_path = copy_test_lib("test_shared", "test_shared_reload");
+    let dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .debounce(Duration::from_millis(100))
+        .build()
+        .unwrap();
+
+    let (tx, rx) = channel();
+    let shared = unsafe {
+        SharedDynamicReload::new(dr, move |state, lib| {
+            if let UpdateState::After = state {
+                tx.send(lib.unwrap().generation).unwrap();
+            }
+        })
+    };
+    let handle = unsafe {
+        shared
+            .add_library("test_shared_reload", PlatformName::Yes)
+            .unwrap()
+    };
+
+    // Read the library from another thread while it's being reloaded
+    let reader = {
+        let handle = handle.clone();
+        thread::spawn(move || {
+            let mut fun = unsafe { handle.symbol::<extern "C" fn() -> i32>("shared_fun") }.unwrap();
+            for _ in 0..20 {
+                assert_eq!((fun.get().unwrap())(), 48);
+                thread::sleep(Duration::from_millis(10));
+            }
+        })
+    };
+
+    change_lib(&original_path);
+
+    let generation = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert!(generation >= 1);
+    assert!(handle.current().unwrap().generation >= 1);
+    assert_eq!(shared.lock().libs.len(), 1);
+
+    reader.join().unwrap();
+}
+
+#[test]
+fn test_held_lib_outlives_reload() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let original_path = copy_test_lib("test_shared", "test_held_reload");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .unload_policy(UnloadPolicy::Unload)
+        .build()
+        .unwrap();
+    let lib = unsafe {
+        dr.add_library("test_held_reload", PlatformName::Yes)
+            .unwrap()
+    };
+
+    change_lib(&original_path);
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(notify_callback.after_update_done);
+    assert_eq!(dr.libs[0].lib.generation, 1);
+
+    // The old version stays loaded while it's held
+    let fun: Symbol<fn() -> i32> = unsafe { lib.lib.get(b"shared_fun\0") }.unwrap();
+    assert_eq!(fun(), 48);
+    assert!(lib.loaded_path.exists());
+}
+
+#[test]
+fn test_call_guard_delays_unload() {
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .unload_policy(UnloadPolicy::Unload)
+        .unload_timeout(Duration::from_secs(5))
+        .build()
+        .unwrap();
+    let lib = unsafe { dr.add_library("test_shared", PlatformName::Yes).unwrap() };
+    let handle = dr.handle(&lib).unwrap();
+    drop(lib);
+
+    let (tx, rx) = channel();
+    let t = thread::spawn(move || {
+        // Only the guard and the function are kept
+        let (guard, fun) = {
+            let lib = handle.current().unwrap();
+            let fun: Symbol<fn() -> i32> = unsafe { lib.lib.get(b"shared_fun\0") }.unwrap();
+            (lib.enter(), *fun)
+        };
+
+        tx.send(()).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(fun(), 48);
+        drop(guard);
+    });
+
+    rx.recv().unwrap();
+    let start = std::time::Instant::now();
+    let state = dr
+        .remove_library_by_name("test_shared", PlatformName::Yes)
+        .unwrap();
+
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(state, RemoveState::Unloaded);
+    t.join().unwrap();
+}
+
+#[test]
+fn test_call_guard_timeout_leaks() {
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .unload_policy(UnloadPolicy::Unload)
+        .unload_timeout(Duration::from_millis(50))
+        .build()
+        .unwrap();
+    let lib = unsafe { dr.add_library("test_shared", PlatformName::Yes).unwrap() };
+    let guard = lib.enter();
+
+    assert_eq!(dr.remove_library(&lib).unwrap(), RemoveState::Leaked);
+    drop(guard);
+}
+
+#[test]
+fn test_call_guard_reload_timeout() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let original_path = copy_test_lib("test_shared", "test_guard_reload");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .unload_policy(UnloadPolicy::Unload)
+        .unload_timeout(Duration::from_millis(50))
+        .build()
+        .unwrap();
+    let lib = unsafe {
+        dr.add_library("test_guard_reload", PlatformName::Yes)
+            .unwrap()
+    };
+    let guard = lib.enter();
+    drop(lib);
+
+    change_lib(&original_path);
+
+    let start = std::time::Instant::now();
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &TestNotifyCallback::update_call,
+            &mut notify_callback,
+        );
+    }
+
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert!(notify_callback.after_update_done);
+    assert!(notify_callback.unload_timed_out);
+    assert_eq!(dr.libs[0].lib.generation, 1);
+    drop(guard);
+}
+
+#[test]
+fn test_debouncer_waits_for_quiet() {
+    let path = copy_test_lib("test_shared", "test_debounce_quiet");
+    let debounce = Duration::from_millis(100);
+    let mut debouncer = Debouncer::new(debounce);
+    let now = std::time::Instant::now();
+
+    debouncer.push(RawEvent::Modified(path.clone()), now);
+    debouncer.push(RawEvent::Modified(path.clone()), now);
+    assert!(debouncer.flush(now).is_empty());
+
+    let events = debouncer.flush(now + debounce);
+    assert_eq!(events.len(), 1);
+    assert!(matches!(&events[0], WatchEvent::Changed(p) if p == &path));
+}
+
+#[test]
+fn test_debouncer_write_closed() {
+    let path = copy_test_lib("test_shared", "test_debounce_closed");
+    let mut debouncer = Debouncer::new(Duration::from_secs(10));
+    let now = std::time::Instant::now();
+
+    debouncer.push(RawEvent::Modified(path.clone()), now);
+    debouncer.push(RawEvent::WriteClosed(path.clone()), now);
+
+    let events = debouncer.flush(now);
+    assert_eq!(events.len(), 1);
+    assert!(matches!(&events[0], WatchEvent::Changed(p) if p == &path));
+}
+
+#[test]
+fn test_debouncer_still_writing() {
+    let path = copy_test_lib("test_shared", "test_debounce_writing");
+    let debounce = Duration::from_millis(100);
+    let mut debouncer = Debouncer::new(debounce);
+    let now = std::time::Instant::now();
+
+    debouncer.push(RawEvent::Modified(path.clone()), now);
+    change_lib(&path);
+
+    // The size changed since the event so wait another round
+    assert!(debouncer.flush(now + debounce).is_empty());
+    assert_eq!(debouncer.flush(now + debounce * 2).len(), 1);
+}
+
+#[test]
+fn test_debouncer_removed() {
+    let path = copy_test_lib("test_shared", "test_debounce_removed");
+    let missing = path.with_file_name("test_debounce_missing");
+    let debounce = Duration::from_millis(100);
+    let mut debouncer = Debouncer::new(debounce);
+    let now = std::time::Instant::now();
+
+    // Removed and written again, as some tools replace files
+    debouncer.push(RawEvent::Removed(path.clone()), now);
+    debouncer.push(RawEvent::Removed(missing.clone()), now);
+    debouncer.push(RawEvent::Renamed(missing.clone(), path.clone()), now);
+    debouncer.push(RawEvent::Rescan, now);
+
+    let events = debouncer.flush(now);
+    assert_eq!(events.len(), 1);
+    assert!(matches!(events[0], WatchEvent::Rescan));
+
+    let events = debouncer.flush(now + debounce);
+    assert_eq!(events.len(), 2);
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, WatchEvent::Changed(p) if p == &path)));
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, WatchEvent::Renamed(from, to) if from == &missing && to == &path)));
+}
+
+#[test]
+fn test_update_watch_events() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let path_a = copy_test_lib("test_shared", "test_watch_events_a");
+    let path_b = copy_test_lib("test_shared", "test_watch_events_b");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    unsafe {
+        dr.add_library("test_watch_events_a", PlatformName::Yes)
+            .unwrap();
+        dr.add_library("test_watch_events_b", PlatformName::Yes)
+            .unwrap();
+    }
+
+    let (tx, rx) = channel();
+    dr.watch_recv = rx;
+
+    // Removals keep the loaded version
+    tx.send(WatchEvent::Removed(path_a.clone())).unwrap();
+    tx.send(WatchEvent::Error(Error::Find("watch".into())))
+        .unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert!(notify_callback.watch_error);
+    assert!(notify_callback.removed);
+    assert_eq!(notify_callback.reload_count, 0);
+    assert_eq!(dr.libs.len(), 2);
+
+    // A rescan only reloads libraries that changed
+    change_lib(&path_b);
+    tx.send(WatchEvent::Rescan).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.reload_count, 1);
+    assert_eq!(dr.libs[0].lib.generation, 0);
+    assert_eq!(dr.libs[1].lib.generation, 1);
+
+    // Renaming a file over a library reloads it
+    change_lib(&path_a);
+    let tmp_path = path_a.with_file_name("test_watch_events_tmp");
+    tx.send(WatchEvent::Renamed(tmp_path, path_a)).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.reload_count, 2);
+    assert_eq!(dr.libs[0].lib.generation, 1);
+}
+
+#[test]
+fn test_delete_policy_unload() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let path = copy_test_lib("test_shared", "test_delete_unload");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .delete_policy(DeletePolicy::Unload)
+        .build()
+        .unwrap();
+    let lib = unsafe {
+        dr.add_library("test_delete_unload", PlatformName::Yes)
+            .unwrap()
+    };
+    let handle = dr.handle(&lib).unwrap();
+    drop(lib);
+
+    let (tx, rx) = channel();
+    dr.watch_recv = rx;
+
+    tx.send(WatchEvent::Removed(path.clone())).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert!(notify_callback.removed);
+    assert!(dr.libs.is_empty());
+    assert!(handle.current().is_none());
+
+    // The library is no longer tracked
+    tx.send(WatchEvent::Changed(path)).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.reload_count, 0);
+    assert!(dr.libs.is_empty());
+}
+
+#[test]
+fn test_delete_policy_wait() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let path = copy_test_lib("test_shared", "test_delete_wait");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .delete_policy(DeletePolicy::Wait)
+        .build()
+        .unwrap();
+    let lib = unsafe {
+        dr.add_library("test_delete_wait", PlatformName::Yes)
+            .unwrap()
+    };
+    let handle = dr.handle(&lib).unwrap();
+    let id = lib.id;
+    drop(lib);
+
+    let (tx, rx) = channel();
+    dr.watch_recv = rx;
+
+    fs::remove_file(&path).unwrap();
+    tx.send(WatchEvent::Removed(path.clone())).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert!(notify_callback.removed);
+    assert!(dr.libs.is_empty());
+    assert!(handle.current().is_none());
+
+    // Loaded again when the file comes back
+    copy_test_lib("test_shared", "test_delete_wait");
+    tx.send(WatchEvent::Changed(path)).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.reload_count, 1);
+    assert_eq!(dr.libs.len(), 1);
+    assert!(dr.waiting.is_empty());
+
+    let current = handle.current().unwrap();
+    assert_eq!(current.id, id);
+    assert_eq!(current.generation, 1);
+}
+
+#[test]
+fn test_update_renamed_away() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let path = copy_test_lib("test_shared", "test_renamed_away");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    unsafe {
+        dr.add_library("test_renamed_away", PlatformName::Yes)
+            .unwrap();
+    }
+
+    let (tx, rx) = channel();
+    dr.watch_recv = rx;
+
+    let to = path.with_file_name("test_renamed_away_old");
+    tx.send(WatchEvent::Renamed(path.clone(), to.clone()))
+        .unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    // The loaded version is kept by default
+    assert_eq!(notify_callback.renamed, Some(to.clone()));
+    assert_eq!(dr.libs.len(), 1);
+
+    // Moved away and replaced in one go only reloads
+    notify_callback.renamed = None;
+    change_lib(&path);
+    tx.send(WatchEvent::Renamed(path.clone(), to)).unwrap();
+    tx.send(WatchEvent::Changed(path)).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.renamed, None);
+    assert_eq!(notify_callback.reload_count, 1);
+}
+
+#[test]
+fn test_add_directory() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let path_a = copy_test_lib_to_dir("test_add_dir", "test_dir_plugin_a");
+    let path_b = copy_test_lib_to_dir("test_add_dir", "test_dir_plugin_b");
+    let path_c = path_a.with_file_name(DynamicReload::get_dynamiclib_name("test_dir_plugin_c"));
+    let other = copy_test_lib_to_dir("test_add_dir", "test_dir_other");
+    let _ = fs::remove_file(&path_c);
+    fs::write(
+        path_a.with_file_name("test_dir_plugin_notes.txt"),
+        "not a library",
+    )
+    .unwrap();
+    let mut dr = DynamicReload::builder()
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+
+    let libs = unsafe {
+        dr.add_directory(path_a.parent().unwrap(), "*test_dir_plugin_*")
+            .unwrap()
+    };
+
+    assert_eq!(libs.len(), 2);
+    assert_eq!(libs[0].original_path, Some(path_a.canonicalize().unwrap()));
+    assert_eq!(libs[1].original_path, Some(path_b.canonicalize().unwrap()));
+    drop(libs);
+
+    let (tx, rx) = channel();
+    dr.watch_recv = rx;
+
+    // Libraries not matching the pattern are ignored
+    tx.send(WatchEvent::Changed(other)).unwrap();
+    fs::copy(&path_a, &path_c).unwrap();
+    tx.send(WatchEvent::Changed(path_c.clone())).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.added_count, 1);
+    assert_eq!(notify_callback.reload_count, 0);
+    assert_eq!(dr.libs.len(), 3);
+
+    // Removed libraries are unloaded
+    let lib_b = dr.libs[1].lib.clone();
+    let handle = dr.handle(&lib_b).unwrap();
+    drop(lib_b);
+    fs::remove_file(&path_b).unwrap();
+    tx.send(WatchEvent::Removed(path_b)).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert!(notify_callback.removed);
+    assert_eq!(dr.libs.len(), 2);
+    assert!(handle.current().is_none());
+
+    // Changes are reloaded as usual
+    change_lib(&path_c);
+    tx.send(WatchEvent::Changed(path_c)).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert_eq!(notify_callback.added_count, 1);
+    assert_eq!(notify_callback.reload_count, 1);
+}
+
+#[test]
+fn test_add_directory_rescan() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let path_a = copy_test_lib_to_dir("test_add_dir_rescan", "test_rescan_plugin_a");
+    let path_b = path_a.with_file_name(DynamicReload::get_dynamiclib_name("test_rescan_plugin_b"));
+    let _ = fs::remove_file(&path_b);
+    let mut dr = DynamicReload::builder()
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+
+    unsafe {
+        dr.add_directory(path_a.parent().unwrap(), "*test_rescan_plugin_*")
+            .unwrap();
+    }
+
+    let (tx, rx) = channel();
+    dr.watch_recv = rx;
+
+    // A rescan finds both the new and the removed library
+    fs::rename(&path_a, &path_b).unwrap();
+    tx.send(WatchEvent::Rescan).unwrap();
+
+    unsafe {
+        dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+    }
+
+    assert!(notify_callback.removed);
+    assert_eq!(notify_callback.added_count, 1);
+    assert_eq!(dr.libs.len(), 1);
+    assert_eq!(
+        dr.libs[0].lib.original_path,
+        Some(path_b.canonicalize().unwrap())
+    );
+}
+
+#[test]
+fn test_add_directory_invalid() {
+    let mut dr = DynamicReload::builder().build().unwrap();
+
+    unsafe {
+        assert!(matches!(
+            dr.add_directory("target/debug", "[plugin"),
+            Err(Error::Pattern(_, _))
+        ));
+        assert!(matches!(
+            dr.add_directory("target/debug/test_add_dir_missing", "*"),
+            Err(Error::Read(_, _))
+        ));
+    }
+}
+
+// Waits for the events sent by a backend until ```done``` returns true
+fn wait_for_events<F>(rx: &std::sync::mpsc::Receiver<RawEvent>, events: &mut Vec<RawEvent>, done: F)
+where
+    F: Fn(&[RawEvent]) -> bool,
+{
+    let start = std::time::Instant::now();
+
+    while !done(events) && start.elapsed() < Duration::from_secs(5) {
+        if let Ok(evt) = rx.recv_timeout(Duration::from_millis(10)) {
+            events.push(evt);
+        }
+    }
+}
+
+#[test]
+fn test_poll_backend() {
+    let dir = tempdir::TempDir::new_in("target/debug", "test_poll").unwrap();
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    fs::write(&path_a, b"a").unwrap();
+
+    let (tx, rx) = channel();
+    let mut backend = PollBackend::new(tx, Duration::from_millis(20));
+    backend.watch(dir.path()).unwrap();
+
+    let modified = |events: &[RawEvent], path: &Path| {
+        events
+            .iter()
+            .any(|e| matches!(e, RawEvent::Modified(p) if p == path))
+    };
+
+    fs::write(&path_b, b"b").unwrap();
+    fs::write(&path_a, b"aa").unwrap();
+
+    let mut events = Vec::new();
+    wait_for_events(&rx, &mut events, |events| {
+        modified(events, &path_a) && modified(events, &path_b)
+    });
+    assert!(modified(&events, &path_a));
+    assert!(modified(&events, &path_b));
+
+    fs::remove_file(&path_a).unwrap();
+
+    let mut events = Vec::new();
+    wait_for_events(&rx, &mut events, |events| !events.is_empty());
+    assert_eq!(events.len(), 1);
+    assert!(matches!(&events[0], RawEvent::Removed(p) if p == &path_a));
+
+    // Nothing is reported once the dir isn't watched
+    backend.unwatch(dir.path()).unwrap();
+    fs::write(&path_b, b"bb").unwrap();
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+    assert!(backend.watch(&dir.path().join("missing")).is_err());
+}
+
+#[test]
+fn test_update_poll_mode() {
+    let mut notify_callback = TestNotifyCallback::default();
+    let original_path = copy_test_lib("test_shared", "test_poll_mode");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .watch_mode(WatchMode::Poll)
+        .poll_interval(Duration::from_millis(50))
+        .debounce(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    unsafe {
+        dr.add_library("test_poll_mode", PlatformName::Yes).unwrap();
+    }
+
+    change_lib(&original_path);
+
+    let start = std::time::Instant::now();
+    while notify_callback.reload_count == 0 && start.elapsed() < Duration::from_secs(5) {
+        unsafe {
+            dr.update(&TestNotifyCallback::update_call, &mut notify_callback);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    assert_eq!(notify_callback.reload_count, 1);
+    assert_eq!(dr.libs[0].lib.generation, 1);
+}
+
+#[test]
+fn test_fingerprint_matches() {
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .api_version(1)
+        .build()
+        .unwrap();
+
+    let lib = unsafe { dr.add_library("test_fingerprint", PlatformName::Yes) }.unwrap();
+    let fun: Symbol<extern "C" fn() -> i32> =
+        unsafe { lib.lib.get(b"fingerprint_value\0") }.unwrap();
+    assert_eq!(fun(), 23);
+}
+
+#[test]
+fn test_fingerprint_api_mismatch() {
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .api_version(2)
+        .build()
+        .unwrap();
+
+    match unsafe { dr.add_library("test_fingerprint", PlatformName::Yes) } {
+        Err(Error::Incompatible(diffs, _)) => {
+            assert_eq!(diffs, "api version is 1 but the host has 2");
+        }
+        other => panic!("expected an incompatible library, got {:?}", other),
+    }
+
+    assert!(dr.libs.is_empty());
+}
+
+mod fingerprinted {
+    crate::declare_fingerprint!(4);
+}
+
+#[test]
+fn test_fingerprint_differences() {
+    let host = BuildFingerprint::new(4);
+    let same =
+        unsafe { fingerprint::differences(&host, &fingerprinted::dr_build_fingerprint, Some(4)) };
+    assert!(same.is_empty());
+
+    let other = BuildFingerprint {
+        rustc: c"rustc 0.1.0".as_ptr(),
+        api_version: 3,
+        ..host
+    };
+
+    let diffs = unsafe { fingerprint::differences(&host, &other, None) };
+    assert_eq!(diffs.len(), 1);
+    assert!(diffs[0].starts_with("rustc is rustc 0.1.0 but the host has rustc "));
+
+    let diffs = unsafe { fingerprint::differences(&host, &other, Some(4)) };
+    assert_eq!(diffs[1], "api version is 3 but the host has 4");
+}
+
+// Copies test_shared with ```patch``` applied
+// to its content
+#[cfg(all(unix, not(target_vendor = "apple")))]
+fn patched_test_lib(name: &str, patch: impl FnOnce(&mut Vec<u8>)) -> PathBuf {
+    let dest = copy_test_lib("test_shared", name);
+    let mut data = fs::read(&dest).unwrap();
+    patch(&mut data);
+    fs::write(&dest, data).unwrap();
+    dest
+}
+
+#[cfg(all(unix, not(target_vendor = "apple")))]
+fn add_patched_lib(name: &str) -> Result<Arc<Lib>> {
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+
+    unsafe { dr.add_library(name, PlatformName::Yes) }
+}
+
+#[test]
+#[cfg(all(unix, not(target_vendor = "apple")))]
+fn test_elf_valid() {
+    let path = get_test_shared_lib();
+    assert!(elf::check(&path, &path).is_ok());
+}
+
+#[test]
+#[cfg(all(unix, not(target_vendor = "apple")))]
+fn test_elf_truncated() {
+    let truncated = patched_test_lib("test_elf_truncated", |data| data.truncate(data.len() / 2));
+    let header_only = patched_test_lib("test_elf_header_only", |data| data.truncate(20));
+
+    // The error names the file that was built, not
+    // the shadow copy that was checked
+    for (name, built) in &[
+        ("test_elf_truncated", truncated),
+        ("test_elf_header_only", header_only),
+    ] {
+        match add_patched_lib(name) {
+            Err(Error::Truncated(path)) => assert_eq!(path, built.canonicalize().unwrap()),
+            other => panic!("expected a truncated library, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+#[cfg(all(unix, not(target_vendor = "apple")))]
+fn test_elf_wrong_architecture() {
+    // e_machine of a machine no host runs on
+    let machine = patched_test_lib("test_elf_machine", |data| {
+        data[18] ^= 0x55;
+        data[19] ^= 0x55;
+    });
+
+    // Flips the class to 32-bit if the host is
+    // 64-bit and the other way around
+    let class = patched_test_lib("test_elf_class", |data| data[4] ^= 3);
+
+    for (name, built) in &[("test_elf_machine", machine), ("test_elf_class", class)] {
+        match add_patched_lib(name) {
+            Err(Error::WrongArchitecture(expected, found, path)) => {
+                assert_ne!(expected, found);
+                assert_eq!(path, built.canonicalize().unwrap());
+            }
+            other => panic!("expected a wrong architecture, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+#[cfg(all(unix, not(target_vendor = "apple")))]
+fn test_elf_not_shared_library() {
+    // ET_EXEC in either byte order
+    let exec = patched_test_lib("test_elf_exec", |data| {
+        data[16] = if data[5] == 1 { 2 } else { 0 };
+        data[17] = if data[5] == 1 { 0 } else { 2 };
+    });
+    patched_test_lib("test_elf_not_elf", |data| data[0] = b'#');
+
+    match add_patched_lib("test_elf_exec") {
+        Err(Error::NotSharedLibrary(path)) => assert_eq!(path, exec.canonicalize().unwrap()),
+        other => panic!("expected an executable, got {:?}", other),
+    }
+
+    match add_patched_lib("test_elf_not_elf") {
+        Err(Error::InvalidElf(reason, _)) => assert_eq!(reason, "no ELF header"),
+        other => panic!("expected an invalid file, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_required_symbols() {
+    copy_test_lib("test_shared", "test_required_symbols");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        RequiredSymbol::from("shared_fun:v1"),
+        RequiredSymbol::new("shared_fun").signature("v1")
+    );
+
+    let lib = unsafe {
+        dr.add_library_with_symbols(
+            "test_required_symbols",
+            PlatformName::Yes,
+            ["shared_fun", "shared_fun:v1"],
+        )
+    };
+    assert!(lib.is_ok());
+
+    let result = unsafe {
+        dr.add_library_with_symbols(
+            "test_required_symbols",
+            PlatformName::Yes,
+            ["shared_fun:v2", "missing_fun"],
+        )
+    };
+
+    match result {
+        Err(Error::MissingSymbols(names)) => {
+            assert_eq!(names, vec!["shared_fun (signature v2)", "missing_fun"]);
+        }
+        other => panic!("expected missing symbols, got {:?}", other),
+    }
+
+    assert_eq!(dr.libs.len(), 1);
+}
+
+#[test]
+fn test_required_symbols_reload() {
+    let original_path = copy_test_lib("test_shared", "test_required_reload");
+    let mut dr = DynamicReload::builder()
+        .search_path("target/debug")
+        .shadow_dir("target/debug")
+        .build()
+        .unwrap();
+    let lib = unsafe {
+        dr.add_library_with_symbols("test_required_reload", PlatformName::Yes, ["shared_fun"])
+            .unwrap()
+    };
+
+    // A new version without shared_fun is refused
+    let fingerprint = get_test_shared_lib()
+        .with_file_name(DynamicReload::get_dynamiclib_name("test_fingerprint"));
+    fs::copy(fingerprint, &original_path).unwrap();
+
+    let mut errors = Vec::new();
+
+    unsafe {
+        dr.reload_lib(
+            0,
+            &original_path,
+            &|errors: &mut Vec<String>, state, _: Option<&Arc<Lib>>| {
+                if let UpdateState::ReloadFailed(Error::MissingSymbols(names)) = state {
+                    errors.extend(names);
+                }
+            },
+            &mut errors,
+        );
+    }
+
+    assert_eq!(errors, vec!["shared_fun"]);
+    assert_eq!(dr.libs[0].lib.generation, 0);
+
+    let fun: Symbol<fn() -> i32> = unsafe { lib.lib.get(b"shared_fun\0") }.unwrap();
+    assert_eq!(fun(), 48);
+}
+
+#[test]
+#[cfg(all(unix, not(target_vendor = "apple")))]
+fn test_elf_exports() {
+    let path = get_test_shared_lib();
+    let exports = elf::exports(&path, &path).unwrap();
+    assert!(exports.contains("shared_fun"));
+    assert!(exports.contains("dr_sig__shared_fun__v1"));
+    assert!(!exports.contains("missing_fun"));
+}
+
+mod signature {
+    #[no_mangle]
+    pub extern "C" fn test_signature_fun() {}
+
+    crate::declare_signature!(test_signature_fun, v3);
+}
@@ -0,0 +1,157 @@
+use crate::debouncer::RawEvent;
+use crate::poll::PollBackend;
+use crate::{Error, Result};
+use notify::{
+    event::{AccessKind, AccessMode, ModifyKind, RenameMode},
+    EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use std::{path::Path, sync::mpsc::Sender, time::Duration};
+
+/// Source of file events for DynamicReload. The
+/// events are sent as ```RawEvent```s to the
+/// debouncer the backend was created with.
+pub(crate) trait WatchBackend: Send {
+    /// Starts watching the files in ```dir```
+    /// (not recursive).
+    fn watch(&mut self, dir: &Path) -> Result<()>;
+
+    /// Stops watching ```dir```.
+    fn unwatch(&mut self, dir: &Path) -> Result<()>;
+}
+
+/// Backend using the native file watcher of the
+/// platform (inotify, FSEvents, etc) through
+/// notify.
+pub(crate) struct NotifyBackend {
+    watcher: RecommendedWatcher,
+}
+
+impl NotifyBackend {
+    pub(crate) fn new(tx: Sender<RawEvent>) -> Result<NotifyBackend> {
+        let watcher = notify::recommended_watcher(move |res| {
+            for evt in Self::raw_events(res) {
+                // The debouncer is gone when
+                // DynamicReload has been dropped
+                let _ = tx.send(evt);
+            }
+        })
+        .map_err(Error::Watcher)?;
+
+        Ok(NotifyBackend { watcher })
+    }
+
+    fn raw_events(res: notify::Result<notify::Event>) -> Vec<RawEvent> {
+        let evt = match res {
+            Ok(evt) => evt,
+            Err(e) => return vec![RawEvent::Error(Error::Watcher(e))],
+        };
+
+        if evt.need_rescan() {
+            return vec![RawEvent::Rescan];
+        }
+
+        let paths = evt.paths;
+
+        match evt.kind {
+            EventKind::Create(_)
+            | EventKind::Modify(ModifyKind::Data(_))
+            | EventKind::Modify(ModifyKind::Any)
+            | EventKind::Any => paths.into_iter().map(RawEvent::Modified).collect(),
+
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+                paths.into_iter().map(RawEvent::WriteClosed).collect()
+            }
+
+            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                paths.into_iter().map(RawEvent::Removed).collect()
+            }
+
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                paths.into_iter().map(RawEvent::Modified).collect()
+            }
+
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if paths.len() == 2 => {
+                let mut paths = paths.into_iter();
+                let from = paths.next().unwrap();
+                let to = paths.next().unwrap();
+                vec![RawEvent::Renamed(from, to)]
+            }
+
+            // Without knowing which side of the
+            // rename this is, check if the file is
+            // still there
+            EventKind::Modify(ModifyKind::Name(_)) => paths
+                .into_iter()
+                .map(|path| match path.exists() {
+                    true => RawEvent::Modified(path),
+                    false => RawEvent::Removed(path),
+                })
+                .collect(),
+
+            // Metadata changes (touch, chmod) and
+            // reads don't change the library
+            EventKind::Modify(_) | EventKind::Access(_) | EventKind::Other => Vec::new(),
+        }
+    }
+}
+
+impl WatchBackend for NotifyBackend {
+    fn watch(&mut self, dir: &Path) -> Result<()> {
+        self.watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(Error::Watcher)
+    }
+
+    fn unwatch(&mut self, dir: &Path) -> Result<()> {
+        self.watcher.unwatch(dir).map_err(Error::Watcher)
+    }
+}
+
+/// Uses the native watcher when possible and
+/// polls the directories it fails to watch (or
+/// all of them if it can't be created at all).
+pub(crate) struct FallbackBackend {
+    native: Option<NotifyBackend>,
+    poll: Option<PollBackend>,
+    tx: Sender<RawEvent>,
+    interval: Duration,
+}
+
+impl FallbackBackend {
+    pub(crate) fn new(tx: Sender<RawEvent>, interval: Duration) -> FallbackBackend {
+        FallbackBackend {
+            native: NotifyBackend::new(tx.clone()).ok(),
+            poll: None,
+            tx,
+            interval,
+        }
+    }
+}
+
+impl WatchBackend for FallbackBackend {
+    fn watch(&mut self, dir: &Path) -> Result<()> {
+        if let Some(native) = self.native.as_mut() {
+            if native.watch(dir).is_ok() {
+                return Ok(());
+            }
+        }
+
+        let (tx, interval) = (&self.tx, self.interval);
+        self.poll
+            .get_or_insert_with(|| PollBackend::new(tx.clone(), interval))
+            .watch(dir)
+    }
+
+    fn unwatch(&mut self, dir: &Path) -> Result<()> {
+        // Only one of them is watching the dir
+        if let Some(native) = self.native.as_mut() {
+            let _ = native.unwatch(dir);
+        }
+
+        if let Some(poll) = self.poll.as_mut() {
+            let _ = poll.unwatch(dir);
+        }
+
+        Ok(())
+    }
+}